@@ -9,6 +9,11 @@ pub trait Key: Ord + Sized {
     /// Return maximum depth for the given the branching factor.
     fn max_depth(branching_factor: usize) -> usize;
 
+    /// Return the number of levels above the leafs a root needs to hold this key.
+    fn height(&self, branching_factor: usize) -> usize {
+        self.to_usize().checked_ilog(branching_factor).unwrap_or(0) as usize
+    }
+
     /// Return the index of this key given a branching factor and depth.
     fn index_at(&self, branching_factor: usize, depth: usize) -> usize {
         let key = self.to_usize();
@@ -71,6 +76,18 @@ mod tests {
         assert_eq!(u32::max_depth(32), 6);
     }
 
+    #[test]
+    fn test_height() {
+        assert_eq!(u64::height(&0, 16), 0);
+        assert_eq!(u64::height(&15, 16), 0);
+        assert_eq!(u64::height(&16, 16), 1);
+        assert_eq!(u64::height(&1000, 16), 2);
+        assert_eq!(u64::height(&u64::MAX, 16), u64::max_depth(16));
+
+        assert_eq!(u32::height(&31, 2), 4);
+        assert_eq!(u32::height(&32, 2), 5);
+    }
+
     #[test]
     fn test_index_at() {
         assert_eq!(u32::index_at(&31, 16, 0), 0);
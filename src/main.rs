@@ -1,17 +1,15 @@
-use tree::set::SrbTreeSet;
-
-mod key;
-mod tree;
+use srb_tree::tree::set::SrbTreeSet;
 
 fn main() {
     let set: SrbTreeSet<_> = (0..100_000).step_by(31).collect();
     let util = set.storage();
-    let bytes = set.storage_bytes();
+    let (mode, bytes) = set.storage_bytes();
 
     eprintln!(
-        "storage util: {:.2}% ({}/{} bytes used)",
+        "storage util ({mode:?}): {:.2}% ({}/{} bytes used)",
         util * 100.0,
         (bytes as f32 * util) as usize,
         bytes,
     );
+    eprintln!("{:.2} bytes per entry", set.stats().bytes_per_entry());
 }
@@ -1,5 +1,48 @@
 const B: usize = 16;
 
+pub mod chunks;
+pub mod concurrent;
+pub mod error;
+pub mod frozen;
 pub mod map;
 mod node;
+pub mod olc;
+#[cfg(feature = "rayon")]
+pub mod par;
+pub mod persistent;
+pub mod published;
+#[cfg(feature = "serde")]
+mod serde_impl;
 pub mod set;
+pub mod sharded;
+mod stats;
+mod storage;
+
+#[cfg(feature = "serde")]
+pub use serde_impl::Strict;
+pub use stats::TreeStats;
+pub use storage::StorageMode;
+
+/// The collections are `Send` and `Sync` whenever their keys, values and allocator are, this
+/// fails to compile if a change to their internals loses that.
+#[allow(dead_code)]
+const _: () = {
+    use allocator_api2::alloc::Allocator;
+
+    fn send_sync<T: Send + Sync>() {}
+
+    fn collections<K: Send + Sync, V: Send + Sync, A: Allocator + Clone + Send + Sync>() {
+        send_sync::<map::SrbTreeMap<K, V, A>>();
+        send_sync::<map::Iter<'_, K, V, A>>();
+        send_sync::<map::Keys<'_, K, V, A>>();
+        send_sync::<map::Values<'_, K, V, A>>();
+        send_sync::<chunks::Chunk<'_, K, V, A>>();
+        send_sync::<set::SrbTreeSet<K, A>>();
+        send_sync::<persistent::PersistentSrbTreeMap<K, V>>();
+        send_sync::<published::SrbTreeMapWriter<K, V>>();
+        send_sync::<published::SrbTreeMapReader<K, V>>();
+        send_sync::<sharded::ShardedSrbTreeMap<K, V>>();
+        send_sync::<concurrent::ConcurrentSrbTreeMap<K, V>>();
+        send_sync::<olc::OlcSrbTreeMap<K, V>>();
+    }
+};
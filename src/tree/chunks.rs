@@ -0,0 +1,242 @@
+use std::slice;
+
+use allocator_api2::alloc::{Allocator, Global};
+
+use super::node::{Node, Pairs, Repr};
+use super::storage::Storage;
+use super::B;
+
+/// A borrowed view of consecutive entries of a map, see [`super::map::SrbTreeMap::chunks`].
+///
+/// A chunk is made of whole subtrees, so it can be iterated without searching for its bounds.
+pub struct Chunk<'a, K, V, A: Allocator + Clone = Global> {
+    inline: &'a [Option<(K, V)>],
+    /// Disjoint subtrees in key order.
+    nodes: Vec<&'a Node<K, V, B, A>>,
+    len: usize,
+}
+
+impl<'a, K, V, A: Allocator + Clone> Chunk<'a, K, V, A> {
+    fn new() -> Self {
+        Self {
+            inline: &[],
+            nodes: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn first(&self) -> Option<(&'a K, &'a V)> {
+        match self.inline.first() {
+            Some(entry) => entry.as_ref().map(|(k, v)| (k, v)),
+            None => self.nodes.iter().find_map(|node| node.first()),
+        }
+    }
+
+    pub fn last(&self) -> Option<(&'a K, &'a V)> {
+        match self.inline.last() {
+            Some(entry) => entry.as_ref().map(|(k, v)| (k, v)),
+            None => self.nodes.iter().rev().find_map(|node| node.last()),
+        }
+    }
+
+    pub fn iter(&self) -> ChunkIter<'a, K, V, A> {
+        ChunkIter {
+            inline: self.inline.iter(),
+            nodes: self.nodes.clone().into_iter(),
+            current: None,
+            len: self.len,
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &'a K> + 'a {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &'a V> + 'a {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+impl<'a, K, V, A: Allocator + Clone> IntoIterator for &Chunk<'a, K, V, A> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = ChunkIter<'a, K, V, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct ChunkIter<'a, K, V, A: Allocator + Clone = Global> {
+    inline: slice::Iter<'a, Option<(K, V)>>,
+    nodes: std::vec::IntoIter<&'a Node<K, V, B, A>>,
+    current: Option<Pairs<'a, K, V, B, A>>,
+    len: usize,
+}
+
+impl<'a, K, V, A: Allocator + Clone> Iterator for ChunkIter<'a, K, V, A> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((k, v)) = self.inline.next().and_then(Option::as_ref) {
+            self.len -= 1;
+            return Some((k, v));
+        }
+
+        loop {
+            if let Some(entry) = self.current.as_mut().and_then(Iterator::next) {
+                self.len -= 1;
+                return Some(entry);
+            }
+
+            self.current = Some(self.nodes.next()?.pairs());
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V, A: Allocator + Clone> ExactSizeIterator for ChunkIter<'_, K, V, A> {}
+
+/// Assigns whole subtrees to `n` chunks so that chunk `i` starts as close as possible to the
+/// entry at `i * len / n`.
+///
+/// Only subtrees which hold a chunk boundary are descended into, so this looks at `O(n · depth)`
+/// nodes and their children but never at the entries of a leaf.
+struct Splitter<'a, K, V, A: Allocator + Clone> {
+    chunks: Vec<Chunk<'a, K, V, A>>,
+    total: usize,
+    /// The number of entries in front of the next subtree.
+    start: usize,
+}
+
+impl<'a, K, V, A: Allocator + Clone> Splitter<'a, K, V, A> {
+    /// The number of entries in front of chunk `idx`.
+    fn boundary(&self, idx: usize) -> usize {
+        idx * self.total / self.chunks.len()
+    }
+
+    /// The chunk the entry at `rank` belongs to.
+    fn chunk_of(&self, rank: usize) -> usize {
+        let n = self.chunks.len();
+        // the largest chunk starting at or before rank
+        (rank * n / self.total..n)
+            .take_while(|idx| self.boundary(*idx) <= rank)
+            .last()
+            .unwrap_or(0)
+    }
+
+    fn push(&mut self, idx: usize, node: &'a Node<K, V, B, A>) {
+        let chunk = &mut self.chunks[idx];
+        chunk.nodes.push(node);
+        chunk.len += node.len;
+        self.start += node.len;
+    }
+
+    fn visit(&mut self, node: &'a Node<K, V, B, A>) {
+        if node.len == 0 {
+            return;
+        }
+
+        let first = self.chunk_of(self.start);
+        let last = self.chunk_of(self.start + node.len - 1);
+        if first == last {
+            return self.push(first, node);
+        }
+
+        match &node.repr {
+            Repr::Internal { children } => {
+                for (_, child) in children.iter() {
+                    self.visit(child);
+                }
+            }
+            // a leaf goes to the chunk holding its middle entry
+            Repr::Leaf { .. } => {
+                let idx = self.chunk_of(self.start + node.len / 2);
+                self.push(idx, node);
+            }
+        }
+    }
+}
+
+/// Splits the entries into `n` chunks of about equal length, see
+/// [`super::map::SrbTreeMap::chunks`].
+pub(super) fn split<K, V, A: Allocator + Clone>(
+    storage: &Storage<K, V, A>,
+    n: usize,
+) -> Vec<Chunk<'_, K, V, A>> {
+    assert!(n != 0, "chunk count must be non-zero");
+
+    let mut chunks: Vec<_> = (0..n).map(|_| Chunk::new()).collect();
+    match storage {
+        Storage::Inline(inline) => {
+            let entries = inline.entries().as_slice();
+            for (idx, chunk) in chunks.iter_mut().enumerate() {
+                chunk.inline = &entries[idx * entries.len() / n..(idx + 1) * entries.len() / n];
+                chunk.len = chunk.inline.len();
+            }
+        }
+        Storage::Trie(trie) if trie.root.len != 0 => {
+            let mut splitter = Splitter {
+                chunks,
+                total: trie.root.len,
+                start: 0,
+            };
+            splitter.visit(&trie.root);
+            chunks = splitter.chunks;
+        }
+        Storage::Trie(_) => {}
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tree::map::SrbTreeMap;
+    use crate::tree::set::SrbTreeSet;
+
+    #[test]
+    fn test_chunks() {
+        for len in [0u32, 3, 8, 100, 5000, 70_000] {
+            let map: SrbTreeMap<u32, u32> = (0..len).map(|k| (k * 5, k)).collect();
+            for n in [1, 2, 3, 7, 16, 64] {
+                let chunks = map.chunks(n);
+                assert_eq!(chunks.len(), n);
+
+                // the chunks hold all entries in order
+                assert!(chunks.iter().flat_map(|chunk| chunk.iter()).eq(map.iter()));
+                assert_eq!(
+                    chunks.iter().map(|chunk| chunk.len()).sum::<usize>(),
+                    map.len()
+                );
+
+                // both ends of a chunk are at most half a leaf off
+                for chunk in &chunks {
+                    assert_eq!(chunk.iter().len(), chunk.len());
+                    let share = map.len() as f64 / n as f64;
+                    assert!((chunk.len() as f64 - share).abs() <= 16.0, "{len} {n}");
+                    assert_eq!(chunk.first(), chunk.iter().next());
+                    assert_eq!(chunk.last(), chunk.iter().last());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_set_chunks() {
+        let set: SrbTreeSet<u64> = (0..1000).map(|v| v * v).collect();
+        let chunks = set.chunks(4);
+        assert!(chunks.iter().flat_map(|chunk| chunk.keys()).eq(set.iter()));
+        assert!(chunks.iter().all(|chunk| chunk.len().abs_diff(250) <= 16));
+    }
+}
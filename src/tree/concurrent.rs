@@ -0,0 +1,411 @@
+use std::array;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crossbeam_epoch::{self as epoch, Atomic, Guard, Owned, Shared};
+
+use crate::key::Key;
+
+use super::B;
+
+enum ConcurrentNode<K, V> {
+    /// Internal nodes know their depth so that readers can tell the height of the root they
+    /// loaded without reading anything else.
+    Internal {
+        depth: usize,
+        children: [Atomic<ConcurrentNode<K, V>>; B],
+    },
+    Leaf {
+        entries: [Atomic<(K, V)>; B],
+    },
+}
+
+impl<K: Key, V> ConcurrentNode<K, V> {
+    fn at(depth: usize) -> Self {
+        if depth == K::max_depth(B) {
+            ConcurrentNode::Leaf {
+                entries: array::from_fn(|_| Atomic::null()),
+            }
+        } else {
+            ConcurrentNode::Internal {
+                depth,
+                children: array::from_fn(|_| Atomic::null()),
+            }
+        }
+    }
+
+    fn depth(&self) -> usize {
+        match self {
+            ConcurrentNode::Internal { depth, .. } => *depth,
+            ConcurrentNode::Leaf { .. } => K::max_depth(B),
+        }
+    }
+}
+
+/// A map which can be read and written from many threads at once, readers never take a lock.
+///
+/// Every pointer in the trie is atomic. Since a key always lands in the same slot, writers
+/// install missing nodes with a single compare-and-swap and swap entries in and out of their
+/// leaf slot. Replaced entries are reclaimed once no reader which may still see them is pinned.
+///
+/// Nodes are never unlinked, a removed entry leaves its path behind until the map is dropped.
+pub struct ConcurrentSrbTreeMap<K, V> {
+    root: Atomic<ConcurrentNode<K, V>>,
+    len: AtomicUsize,
+}
+
+impl<K, V> ConcurrentSrbTreeMap<K, V> {
+    pub const fn new() -> Self {
+        Self {
+            root: Atomic::null(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of entries, this may be stale by the time it is used if other threads
+    /// are writing.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Pins the current thread, entries borrowed through the guard stay valid until it is dropped.
+    pub fn pin(&self) -> Guard {
+        epoch::pin()
+    }
+
+    /// Returns the entries in order, entries written while iterating may or may not be seen.
+    pub fn iter<'g>(&'g self, guard: &'g Guard) -> Iter<'g, K, V> {
+        // SAFETY: nodes are only freed when the map is dropped
+        let root = unsafe { self.root.load(Ordering::Acquire, guard).as_ref() };
+        Iter {
+            stack: root.map(|root| (root, 0)).into_iter().collect(),
+            guard,
+        }
+    }
+}
+
+impl<K, V> Default for ConcurrentSrbTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Key, V> ConcurrentSrbTreeMap<K, V> {
+    /// Returns the slot of the given key if the nodes on its path exist.
+    fn find_slot<'g>(&'g self, key: &K, guard: &'g Guard) -> Option<&'g Atomic<(K, V)>> {
+        // SAFETY: nodes are only freed when the map is dropped
+        let mut node = unsafe { self.root.load(Ordering::Acquire, guard).as_ref() }?;
+        if key.height(B) > K::max_depth(B) - node.depth() {
+            return None;
+        }
+
+        loop {
+            match node {
+                ConcurrentNode::Internal { depth, children } => {
+                    let child = children[key.index_at(B, *depth)].load(Ordering::Acquire, guard);
+                    // SAFETY: see above
+                    node = unsafe { child.as_ref() }?;
+                }
+                ConcurrentNode::Leaf { entries } => {
+                    return Some(&entries[key.index_at(B, K::max_depth(B))]);
+                }
+            }
+        }
+    }
+
+    pub fn get<'g>(&'g self, key: &K, guard: &'g Guard) -> Option<(&'g K, &'g V)> {
+        let entry = self.find_slot(key, guard)?.load(Ordering::Acquire, guard);
+        // SAFETY: replaced entries are only freed once every guard pinned before is dropped
+        unsafe { entry.as_ref() }.map(|(k, v)| (k, v))
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key, &epoch::pin()).is_some()
+    }
+}
+
+/// Writes hand replaced entries to the epoch collector, which may drop them on any thread once
+/// no reader can see them anymore, after the borrows they hold may have ended.
+impl<K: Key + Send + 'static, V: Send + 'static> ConcurrentSrbTreeMap<K, V> {
+    /// Returns the node in `slot`, installing an empty one at the given depth if there is none.
+    fn load_or_install<'g>(
+        slot: &Atomic<ConcurrentNode<K, V>>,
+        depth: usize,
+        guard: &'g Guard,
+    ) -> Shared<'g, ConcurrentNode<K, V>> {
+        let current = slot.load(Ordering::Acquire, guard);
+        if !current.is_null() {
+            return current;
+        }
+
+        let new = Owned::new(ConcurrentNode::at(depth));
+        match slot.compare_exchange(
+            Shared::null(),
+            new,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+            guard,
+        ) {
+            Ok(new) => new,
+            // another writer was first, our node was never shared and is dropped with the error
+            Err(err) => err.current,
+        }
+    }
+
+    /// Adds levels above the root until it is at least at the given height and returns it.
+    fn grow<'g>(&self, height: usize, guard: &'g Guard) -> Shared<'g, ConcurrentNode<K, V>> {
+        let depth = K::max_depth(B) - height;
+        loop {
+            let root = self.root.load(Ordering::Acquire, guard);
+            // SAFETY: nodes are only freed when the map is dropped
+            let new = match unsafe { root.as_ref() } {
+                None => ConcurrentNode::at(depth),
+                Some(node) if node.depth() <= depth => return root,
+                Some(node) => {
+                    let children = array::from_fn(|digit| match digit {
+                        0 => Atomic::from(root),
+                        _ => Atomic::null(),
+                    });
+                    ConcurrentNode::Internal {
+                        depth: node.depth() - 1,
+                        children,
+                    }
+                }
+            };
+
+            // dropping a node that lost the race doesn't drop its children, so the old root stays
+            let _ = self.root.compare_exchange(
+                root,
+                Owned::new(new),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+                guard,
+            );
+        }
+    }
+
+    /// Inserts a key value pair, returning whether the key is new.
+    ///
+    /// A previous entry for the key is dropped once no reader can see it anymore.
+    pub fn insert(&self, key: K, value: V) -> bool {
+        let guard = &epoch::pin();
+        let mut node = self.grow(key.height(B), guard);
+
+        loop {
+            // SAFETY: nodes are only freed when the map is dropped
+            match unsafe { node.deref() } {
+                ConcurrentNode::Internal { depth, children } => {
+                    let slot = &children[key.index_at(B, *depth)];
+                    node = Self::load_or_install(slot, depth + 1, guard);
+                }
+                ConcurrentNode::Leaf { entries } => {
+                    let slot = &entries[key.index_at(B, K::max_depth(B))];
+                    let old = slot.swap(Owned::new((key, value)), Ordering::AcqRel, guard);
+                    if old.is_null() {
+                        self.len.fetch_add(1, Ordering::Relaxed);
+                        return true;
+                    }
+
+                    // SAFETY: the swap took the entry out of its slot, which was the only pointer
+                    // to it, so only readers pinned before the swap can still reach it. The
+                    // bounds on this impl allow dropping it later on another thread.
+                    unsafe { guard.defer_destroy(old) };
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Removes a key, returning whether it was present.
+    pub fn remove(&self, key: &K) -> bool {
+        let guard = &epoch::pin();
+        let Some(slot) = self.find_slot(key, guard) else {
+            return false;
+        };
+
+        let old = slot.swap(Shared::null(), Ordering::AcqRel, guard);
+        if old.is_null() {
+            return false;
+        }
+
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        // SAFETY: the swap nulled the only pointer to the entry, so only readers pinned before
+        // it can still reach the entry, see `insert`
+        unsafe { guard.defer_destroy(old) };
+        true
+    }
+}
+
+impl<K, V> Drop for ConcurrentSrbTreeMap<K, V> {
+    fn drop(&mut self) {
+        /// Frees a node and everything below it.
+        ///
+        /// # Safety
+        ///
+        /// No other thread may access the node anymore.
+        unsafe fn free<K, V>(node: Shared<'_, ConcurrentNode<K, V>>, guard: &Guard) {
+            let node = unsafe { node.into_owned() };
+            match &*node {
+                ConcurrentNode::Internal { children, .. } => {
+                    for child in children {
+                        let child = child.load(Ordering::Relaxed, guard);
+                        if !child.is_null() {
+                            unsafe { free(child, guard) };
+                        }
+                    }
+                }
+                ConcurrentNode::Leaf { entries } => {
+                    for entry in entries {
+                        let entry = entry.load(Ordering::Relaxed, guard);
+                        if !entry.is_null() {
+                            drop(unsafe { entry.into_owned() });
+                        }
+                    }
+                }
+            }
+        }
+
+        // SAFETY: `&mut self` means that no other thread can access the map anymore
+        unsafe {
+            let guard = epoch::unprotected();
+            let root = self.root.load(Ordering::Relaxed, guard);
+            if !root.is_null() {
+                free(root, guard);
+            }
+        }
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for ConcurrentSrbTreeMap<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter(&self.pin())).finish()
+    }
+}
+
+impl<K: Key + Send + 'static, V: Send + 'static> Extend<(K, V)> for ConcurrentSrbTreeMap<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Key + Send + 'static, V: Send + 'static> FromIterator<(K, V)>
+    for ConcurrentSrbTreeMap<K, V>
+{
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut this = Self::new();
+        this.extend(iter);
+        this
+    }
+}
+
+pub struct Iter<'g, K, V> {
+    /// The nodes on the path to the next entry and the next digit to visit in each of them.
+    stack: Vec<(&'g ConcurrentNode<K, V>, usize)>,
+    guard: &'g Guard,
+}
+
+impl<'g, K, V> Iterator for Iter<'g, K, V> {
+    type Item = (&'g K, &'g V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, digit) = self.stack.last_mut()?;
+            if *digit == B {
+                self.stack.pop();
+                continue;
+            }
+
+            let (node, current) = (*node, *digit);
+            *digit += 1;
+
+            match node {
+                ConcurrentNode::Internal { children, .. } => {
+                    let child = children[current].load(Ordering::Acquire, self.guard);
+                    // SAFETY: nodes are only freed when the map is dropped
+                    if let Some(child) = unsafe { child.as_ref() } {
+                        self.stack.push((child, 0));
+                    }
+                }
+                ConcurrentNode::Leaf { entries } => {
+                    let entry = entries[current].load(Ordering::Acquire, self.guard);
+                    // SAFETY: entries are only freed once every guard pinned before is dropped
+                    if let Some((k, v)) = unsafe { entry.as_ref() } {
+                        return Some((k, v));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_insert_remove() {
+        let map = ConcurrentSrbTreeMap::new();
+        let mut expected = BTreeMap::new();
+
+        for key in (0..5000u32).map(|k| k.wrapping_mul(2_654_435_761) % 100_000) {
+            assert_eq!(map.insert(key, key), expected.insert(key, key).is_none());
+        }
+        for key in (0..5000u32).step_by(3).map(|k| k * 20) {
+            assert_eq!(map.remove(&key), expected.remove(&key).is_some());
+        }
+
+        let guard = map.pin();
+        assert_eq!(map.len(), expected.len());
+        assert!(map.iter(&guard).eq(expected.iter()));
+        assert_eq!(map.get(&u32::MAX, &guard), None);
+        assert!(!map.remove(&u32::MAX));
+    }
+
+    #[test]
+    fn test_threads() {
+        let map = ConcurrentSrbTreeMap::new();
+        map.insert(0u64, 0u64);
+
+        thread::scope(|scope| {
+            for writer in 0..4u64 {
+                let map = &map;
+                scope.spawn(move || {
+                    // writers share the upper levels and race for them as the trie grows
+                    for key in (0..20_000).map(|k| k * 4 + writer) {
+                        map.insert(key, key);
+                        if key % 3 == 0 {
+                            map.insert(key, key + 1);
+                        }
+                    }
+                });
+            }
+
+            for _ in 0..4 {
+                let map = &map;
+                scope.spawn(move || {
+                    for key in (0..80_000).rev() {
+                        let guard = map.pin();
+                        if let Some((k, v)) = map.get(&key, &guard) {
+                            assert_eq!(k, &key);
+                            assert!(*v == key || *v == key + 1);
+                        }
+                    }
+                });
+            }
+        });
+
+        let guard = map.pin();
+        assert_eq!(map.len(), 80_000);
+        assert!(map.iter(&guard).map(|(k, _)| *k).eq(0..80_000));
+        assert_eq!(map.get(&3, &guard), Some((&3, &4)));
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt::Display;
+
+use allocator_api2::alloc::AllocError;
+
+/// An error returned by the fallible insertion methods of the tree collections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertError {
+    /// The key is larger than the largest key of the tree's universe.
+    OutOfRange,
+    /// A node needed for the key could not be allocated, the tree is left unchanged.
+    Alloc,
+}
+
+impl Display for InsertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InsertError::OutOfRange => write!(f, "key is outside of the tree's universe"),
+            InsertError::Alloc => write!(f, "memory allocation failed"),
+        }
+    }
+}
+
+impl std::error::Error for InsertError {}
+
+impl From<AllocError> for InsertError {
+    fn from(_: AllocError) -> Self {
+        InsertError::Alloc
+    }
+}
+
+/// An error returned when building a tree from entries whose keys are not strictly ascending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildError {
+    /// The key at the given position of the input is smaller than the key before it.
+    Unsorted { index: usize },
+    /// The key at the given position of the input is equal to the key before it.
+    Duplicate { index: usize },
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::Unsorted { index } => {
+                write!(
+                    f,
+                    "key at position {index} is smaller than the key before it"
+                )
+            }
+            BuildError::Duplicate { index } => {
+                write!(
+                    f,
+                    "key at position {index} is a duplicate of the key before it"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// An error returned when opening bytes which don't hold a valid frozen tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrozenError {
+    /// The bytes end before the header does.
+    Truncated,
+    /// The bytes don't start with the magic number of the format.
+    Magic,
+    /// The format version is not supported by this version of the crate.
+    Version { found: u16 },
+    /// The bytes hold a set where a map was expected or the other way around.
+    Kind,
+    /// The header field or node at the given offset is malformed or out of bounds.
+    Corrupt { offset: usize },
+}
+
+impl Display for FrozenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrozenError::Truncated => write!(f, "frozen tree is shorter than its header"),
+            FrozenError::Magic => write!(f, "bytes don't hold a frozen tree"),
+            FrozenError::Version { found } => {
+                write!(f, "frozen tree has unsupported format version {found}")
+            }
+            FrozenError::Kind => write!(
+                f,
+                "frozen tree holds a set where a map was expected or the other way around"
+            ),
+            FrozenError::Corrupt { offset } => {
+                write!(f, "frozen tree is corrupt at offset {offset}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FrozenError {}
@@ -0,0 +1,634 @@
+use std::fmt::Debug;
+use std::ops::{Bound, RangeBounds};
+
+use crate::key::Key;
+
+use super::error::FrozenError;
+use super::B;
+
+// A frozen tree is laid out as
+//
+// - a header of `HEADER` bytes: the magic number, the format version as `u16`, the `Kind` as
+//   `u8`, the height as `u8`, the number of entries as `u64`, the offset of the root and the end
+//   of the values as `u32`, all little endian,
+// - the values of a map in key order,
+// - the nodes, children before their parent so that the root comes last.
+//
+// Every node starts with the `u16` mask of its digits. Internal nodes follow it with the `u32`
+// offset of each child, leafs of a map with the `u32` offset and length of each value. All
+// offsets are relative to the start of the bytes, so they can be mapped at any address.
+
+const MAGIC: [u8; 4] = *b"SRBT";
+const VERSION: u16 = 1;
+const HEADER: usize = 24;
+
+/// The number of key bits consumed by each level.
+const BITS: u32 = B.ilog2();
+
+// node masks have a bit per digit, changing the fanout changes the format
+const _: () = assert!(B == u16::BITS as usize, "frozen node masks are `u16`");
+
+/// The height of a tree holding `u64::MAX`, keys are frozen as `u64`.
+const MAX_HEIGHT: u32 = u64::BITS / BITS - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Kind {
+    Set = 0,
+    Map = 1,
+}
+
+fn offset(value: usize) -> [u8; 4] {
+    u32::try_from(value)
+        .expect("frozen trees are limited to 4 GiB")
+        .to_le_bytes()
+}
+
+/// Returns the key a frozen tree stores for `key`.
+pub(super) fn key<K: Key>(key: &K) -> u64 {
+    key.to_usize()
+        .try_into()
+        .expect("frozen keys are limited to 64 bits")
+}
+
+struct Writer {
+    out: Vec<u8>,
+    kind: Kind,
+    height: u32,
+    /// The offset of the next value, values are written in key order just like the leafs.
+    value: usize,
+}
+
+impl Writer {
+    /// Writes the subtree holding the given entries, returning the offset of its root.
+    fn node(&mut self, level: u32, entries: &[(u64, &[u8])]) -> usize {
+        let shift = BITS * (self.height - level);
+        let digit = |key: u64| (key >> shift) as usize % B;
+
+        let mut mask = 0u16;
+        let mut table = Vec::new();
+        if level == self.height {
+            for (key, value) in entries {
+                mask |= 1 << digit(*key);
+                if self.kind == Kind::Map {
+                    table.extend(offset(self.value));
+                    table.extend(offset(value.len()));
+                    self.value += value.len();
+                }
+            }
+        } else {
+            for run in entries.chunk_by(|a, b| digit(a.0) == digit(b.0)) {
+                mask |= 1 << digit(run[0].0);
+                let child = self.node(level + 1, run);
+                table.extend(offset(child));
+            }
+        }
+
+        let node = self.out.len();
+        self.out.extend(mask.to_le_bytes());
+        self.out.extend(table);
+        node
+    }
+}
+
+/// Writes entries with strictly ascending keys into a frozen tree, set entries have no value.
+pub(super) fn freeze(entries: &[(u64, &[u8])], kind: Kind) -> Vec<u8> {
+    let height = entries
+        .last()
+        .map_or(0, |(key, _)| key.checked_ilog(B as u64).unwrap_or(0));
+
+    let mut writer = Writer {
+        out: vec![0; HEADER],
+        kind,
+        height,
+        value: HEADER,
+    };
+    if kind == Kind::Map {
+        for (_, value) in entries {
+            writer.out.extend_from_slice(value);
+        }
+    }
+
+    let values_end = writer.out.len();
+    let root = match entries {
+        [] => 0,
+        entries => writer.node(0, entries),
+    };
+
+    let mut out = writer.out;
+    out[..4].copy_from_slice(&MAGIC);
+    out[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    out[6] = kind as u8;
+    out[7] = height as u8;
+    out[8..16].copy_from_slice(&(entries.len() as u64).to_le_bytes());
+    out[16..20].copy_from_slice(&offset(root));
+    out[20..24].copy_from_slice(&offset(values_end));
+    out
+}
+
+/// The bytes of a frozen tree which passed validation.
+#[derive(Clone, Copy)]
+struct Frozen<'a> {
+    bytes: &'a [u8],
+    kind: Kind,
+    height: u32,
+    len: u64,
+    /// The offset of the root, zero if the tree is empty.
+    root: usize,
+    values_end: usize,
+}
+
+impl<'a> Frozen<'a> {
+    fn open(bytes: &'a [u8], kind: Kind) -> Result<Self, FrozenError> {
+        let header = bytes.get(..HEADER).ok_or(FrozenError::Truncated)?;
+        if header[..4] != MAGIC {
+            return Err(FrozenError::Magic);
+        }
+
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != VERSION {
+            return Err(FrozenError::Version { found: version });
+        }
+        if header[6] != kind as u8 {
+            return Err(FrozenError::Kind);
+        }
+
+        let this = Self {
+            bytes,
+            kind,
+            height: header[7].into(),
+            len: u64::from_le_bytes(header[8..16].try_into().unwrap()),
+            root: read_u32(header, 16),
+            values_end: read_u32(header, 20),
+        };
+
+        if this.height > MAX_HEIGHT {
+            return Err(FrozenError::Corrupt { offset: 7 });
+        }
+        let values = match kind {
+            Kind::Map => (HEADER..=bytes.len()).contains(&this.values_end),
+            Kind::Set => this.values_end == HEADER,
+        };
+        if !values {
+            return Err(FrozenError::Corrupt { offset: 20 });
+        }
+
+        let mut len = 0;
+        let end = match this.root {
+            0 => this.values_end,
+            root => this.validate(root, 0, this.values_end, &mut len)?,
+        };
+        if end != bytes.len() {
+            return Err(FrozenError::Corrupt { offset: end });
+        }
+        if len != this.len {
+            return Err(FrozenError::Corrupt { offset: 8 });
+        }
+
+        Ok(this)
+    }
+
+    /// Checks the node at `offset` and all nodes below it, returning where the node ends.
+    ///
+    /// The subtrees of a node's children have to fill the bytes from `start` up to the node itself
+    /// without gaps, so no two nodes overlap and every node is checked once.
+    fn validate(
+        &self,
+        offset: usize,
+        level: u32,
+        start: usize,
+        len: &mut u64,
+    ) -> Result<usize, FrozenError> {
+        let corrupt = FrozenError::Corrupt { offset };
+        if offset < start || offset + 2 > self.bytes.len() {
+            return Err(corrupt);
+        }
+
+        let mask = read_u16(self.bytes, offset);
+        let count = mask.count_ones() as usize;
+        let entry = match (level == self.height, self.kind) {
+            (false, _) => 4,
+            (true, Kind::Map) => 8,
+            (true, Kind::Set) => 0,
+        };
+        let end = offset + 2 + count * entry;
+        if mask == 0 || end > self.bytes.len() {
+            return Err(corrupt);
+        }
+
+        if level == self.height {
+            for idx in (0..count).filter(|_| self.kind == Kind::Map) {
+                let (value, value_len) = self.value_at(offset, idx);
+                if value < HEADER || value + value_len > self.values_end {
+                    return Err(corrupt);
+                }
+            }
+
+            *len += count as u64;
+            return if offset == start {
+                Ok(end)
+            } else {
+                Err(corrupt)
+            };
+        }
+
+        let mut cursor = start;
+        for idx in 0..count {
+            cursor = self.validate(self.child_at(offset, idx), level + 1, cursor, len)?;
+        }
+
+        if cursor == offset {
+            Ok(end)
+        } else {
+            Err(corrupt)
+        }
+    }
+
+    fn child_at(&self, node: usize, idx: usize) -> usize {
+        read_u32(self.bytes, node + 2 + 4 * idx)
+    }
+
+    fn value_at(&self, leaf: usize, idx: usize) -> (usize, usize) {
+        let entry = leaf + 2 + 8 * idx;
+        (read_u32(self.bytes, entry), read_u32(self.bytes, entry + 4))
+    }
+
+    fn value(&self, leaf: usize, idx: usize) -> &'a [u8] {
+        let (value, len) = self.value_at(leaf, idx);
+        &self.bytes[value..value + len]
+    }
+
+    /// Returns the leaf holding the key and the index of the key within it.
+    fn find(&self, key: u64) -> Option<(usize, usize)> {
+        if self.root == 0 || key.checked_shr(BITS * (self.height + 1)).unwrap_or(0) != 0 {
+            return None;
+        }
+
+        let mut node = self.root;
+        for level in 0..=self.height {
+            let digit = (key >> (BITS * (self.height - level))) as usize % B;
+            let mask = read_u16(self.bytes, node);
+            if mask & (1 << digit) == 0 {
+                return None;
+            }
+
+            let idx = (mask & ((1 << digit) - 1)).count_ones() as usize;
+            if level == self.height {
+                return Some((node, idx));
+            }
+            node = self.child_at(node, idx);
+        }
+
+        unreachable!("the last level holds the leafs")
+    }
+
+    fn range<R: RangeBounds<u64>>(&self, range: R) -> Cursor<'a> {
+        let lo = match range.start_bound() {
+            Bound::Included(start) => Some(*start),
+            Bound::Excluded(start) => start.checked_add(1),
+            Bound::Unbounded => Some(0),
+        };
+        let hi = match range.end_bound() {
+            Bound::Included(end) => Some(*end),
+            Bound::Excluded(end) => end.checked_sub(1),
+            Bound::Unbounded => Some(u64::MAX),
+        };
+
+        let mut cursor = Cursor {
+            frozen: *self,
+            stack: Vec::new(),
+            lo: lo.unwrap_or(u64::MAX),
+            hi: hi.unwrap_or(0),
+        };
+        if self.root != 0 && lo.is_some() && hi.is_some() && lo <= hi {
+            let mask = read_u16(self.bytes, self.root);
+            cursor.stack.push((self.root, 0, mask));
+        }
+
+        cursor
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+/// Walks the keys of a frozen tree in `lo..=hi`, only descending into subtrees overlapping it.
+#[derive(Clone)]
+struct Cursor<'a> {
+    frozen: Frozen<'a>,
+    /// The nodes on the path to the next key, with their key prefix and the digits left to visit.
+    stack: Vec<(usize, u64, u16)>,
+    lo: u64,
+    hi: u64,
+}
+
+impl Cursor<'_> {
+    /// Returns the next key together with its leaf and its index in the leaf.
+    fn next(&mut self) -> Option<(u64, usize, usize)> {
+        let height = self.frozen.height;
+        loop {
+            let level = self.stack.len().checked_sub(1)? as u32;
+            let (node, prefix, remaining) = self.stack.last_mut()?;
+            if *remaining == 0 {
+                self.stack.pop();
+                continue;
+            }
+
+            let digit = remaining.trailing_zeros();
+            *remaining &= *remaining - 1;
+
+            let mask = read_u16(self.frozen.bytes, *node);
+            let idx = (mask & ((1 << digit) - 1)).count_ones() as usize;
+            let prefix = *prefix << BITS | u64::from(digit);
+            let below = BITS * (height - level);
+            let (first, last) = (prefix << below, prefix << below | ((1 << below) - 1));
+
+            if first > self.hi {
+                self.stack.clear();
+                return None;
+            }
+            if last < self.lo {
+                continue;
+            }
+
+            if level == height {
+                return Some((first, *node, idx));
+            }
+
+            let child = self.frozen.child_at(*node, idx);
+            let mask = read_u16(self.frozen.bytes, child);
+            self.stack.push((child, prefix, mask));
+        }
+    }
+}
+
+/// A set stored in the frozen format written by [`super::set::SrbTreeSet::freeze`], read in place
+/// from any byte slice such as a memory mapped file.
+///
+/// The bytes are validated once when the set is opened, lookups and iteration don't allocate
+/// besides the iterator's path.
+#[derive(Clone, Copy)]
+pub struct FrozenSrbTreeSet<'a> {
+    frozen: Frozen<'a>,
+}
+
+impl<'a> FrozenSrbTreeSet<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, FrozenError> {
+        Frozen::open(bytes, Kind::Set).map(|frozen| Self { frozen })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frozen.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frozen.len == 0
+    }
+
+    pub fn contains(&self, value: u64) -> bool {
+        self.frozen.find(value).is_some()
+    }
+
+    pub fn iter(&self) -> SetIter<'a> {
+        self.range(..)
+    }
+
+    /// Returns the values in the given range in ascending order.
+    pub fn range<R: RangeBounds<u64>>(&self, range: R) -> SetIter<'a> {
+        SetIter {
+            cursor: self.frozen.range(range),
+        }
+    }
+}
+
+/// A map stored in the frozen format written by [`super::map::SrbTreeMap::freeze`], see
+/// [`FrozenSrbTreeSet`].
+#[derive(Clone, Copy)]
+pub struct FrozenSrbTreeMap<'a> {
+    frozen: Frozen<'a>,
+}
+
+impl<'a> FrozenSrbTreeMap<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, FrozenError> {
+        Frozen::open(bytes, Kind::Map).map(|frozen| Self { frozen })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frozen.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frozen.len == 0
+    }
+
+    pub fn get(&self, key: u64) -> Option<&'a [u8]> {
+        let (leaf, idx) = self.frozen.find(key)?;
+        Some(self.frozen.value(leaf, idx))
+    }
+
+    pub fn contains_key(&self, key: u64) -> bool {
+        self.frozen.find(key).is_some()
+    }
+
+    pub fn iter(&self) -> MapIter<'a> {
+        self.range(..)
+    }
+
+    /// Returns the entries with keys in the given range in ascending order.
+    pub fn range<R: RangeBounds<u64>>(&self, range: R) -> MapIter<'a> {
+        MapIter {
+            cursor: self.frozen.range(range),
+        }
+    }
+}
+
+impl Debug for FrozenSrbTreeSet<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl Debug for FrozenSrbTreeMap<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[derive(Clone)]
+pub struct SetIter<'a> {
+    cursor: Cursor<'a>,
+}
+
+impl Iterator for SetIter<'_> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.next().map(|(key, _, _)| key)
+    }
+}
+
+#[derive(Clone)]
+pub struct MapIter<'a> {
+    cursor: Cursor<'a>,
+}
+
+impl<'a> Iterator for MapIter<'a> {
+    type Item = (u64, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, leaf, idx) = self.cursor.next()?;
+        Some((key, self.cursor.frozen.value(leaf, idx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::tree::map::SrbTreeMap;
+    use crate::tree::set::SrbTreeSet;
+
+    use super::*;
+
+    #[test]
+    fn test_set() {
+        for len in [0u64, 1, 5, 300, 20_000] {
+            let set: SrbTreeSet<u64> = (0..len).map(|v| v * v * 7).collect();
+            let bytes = set.freeze();
+            let frozen = FrozenSrbTreeSet::new(&bytes).unwrap();
+
+            assert_eq!(frozen.len(), set.len());
+            assert_eq!(frozen.is_empty(), set.is_empty());
+            assert!(frozen.iter().eq(set.iter().copied()));
+            for v in (0..len * 3).map(|v| v * v) {
+                assert_eq!(frozen.contains(v * 7), set.get(&(v * 7)).is_some(), "{v}");
+                assert_eq!(frozen.contains(v * 7 + 1), set.get(&(v * 7 + 1)).is_some());
+            }
+
+            let bounds = [0, 1, 7, 63, 700, 1 << 20, u64::MAX];
+            for lo in bounds {
+                for hi in bounds {
+                    let expected = set.iter().copied().filter(|v| (lo..hi).contains(v));
+                    assert!(frozen.range(lo..hi).eq(expected), "{lo}..{hi}");
+                    let expected = set.iter().copied().filter(|v| (lo..=hi).contains(v));
+                    assert!(frozen.range(lo..=hi).eq(expected), "{lo}..={hi}");
+                }
+            }
+            let excluded = (Bound::Excluded(7), Bound::Unbounded);
+            assert!(frozen
+                .range(excluded)
+                .eq(set.iter().copied().filter(|v| *v > 7)));
+        }
+    }
+
+    #[test]
+    fn test_map() {
+        let model: BTreeMap<u32, String> = (0..5000u32)
+            .map(|k| {
+                (
+                    k.wrapping_mul(2_654_435_761) >> 8,
+                    "x".repeat(k as usize % 5),
+                )
+            })
+            .collect();
+        let map: SrbTreeMap<u32, String> = model.clone().into_iter().collect();
+        let bytes = map.freeze();
+        let frozen = FrozenSrbTreeMap::new(&bytes).unwrap();
+
+        assert_eq!(frozen.len(), model.len());
+        assert!(frozen
+            .iter()
+            .eq(model.iter().map(|(k, v)| (u64::from(*k), v.as_bytes()))));
+        for (k, v) in &model {
+            assert_eq!(frozen.get(u64::from(*k)), Some(v.as_bytes()));
+            assert_eq!(
+                frozen.contains_key(u64::from(*k) + 1),
+                model.contains_key(&(k + 1))
+            );
+        }
+        assert_eq!(frozen.get(u64::MAX), None);
+
+        let expected = model
+            .range(1000..1 << 20)
+            .map(|(k, v)| (u64::from(*k), v.as_bytes()));
+        assert!(frozen.range(1000..1 << 20).eq(expected));
+
+        // a map can't be opened as a set and the other way around
+        assert_eq!(
+            FrozenSrbTreeSet::new(&bytes).unwrap_err(),
+            FrozenError::Kind
+        );
+        let set: SrbTreeSet<u8> = [1, 2].into_iter().collect();
+        assert_eq!(
+            FrozenSrbTreeMap::new(&set.freeze()).unwrap_err(),
+            FrozenError::Kind
+        );
+    }
+
+    #[test]
+    fn test_extremes() {
+        let set: SrbTreeSet<u64> = [0, 15, 16, u64::MAX - 1, u64::MAX].into_iter().collect();
+        let bytes = set.freeze();
+        let frozen = FrozenSrbTreeSet::new(&bytes).unwrap();
+        assert_eq!(bytes[7] as u32, MAX_HEIGHT);
+        assert!(frozen.iter().eq(set.iter().copied()));
+        assert!(frozen.range(u64::MAX..).eq([u64::MAX]));
+        assert!(frozen.range(1..u64::MAX).eq([15, 16, u64::MAX - 1]));
+        assert_eq!(
+            frozen
+                .range((Bound::Excluded(u64::MAX), Bound::Unbounded))
+                .next(),
+            None
+        );
+        assert_eq!(format!("{frozen:?}").len(), format!("{set:?}").len());
+
+        let map: SrbTreeMap<u8, Vec<u8>> = SrbTreeMap::new();
+        let bytes = map.freeze();
+        let frozen = FrozenSrbTreeMap::new(&bytes).unwrap();
+        assert!(frozen.is_empty());
+        assert_eq!(frozen.get(0), None);
+        assert_eq!(frozen.iter().next(), None);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let map: SrbTreeMap<u16, [u8; 2]> = (0..150u16).map(|k| (k * 3, k.to_le_bytes())).collect();
+        let bytes = map.freeze();
+        assert!(FrozenSrbTreeMap::new(&bytes).is_ok());
+
+        for len in 0..bytes.len() {
+            let err = FrozenSrbTreeMap::new(&bytes[..len]).unwrap_err();
+            if len < HEADER {
+                assert_eq!(err, FrozenError::Truncated);
+            }
+        }
+
+        let mut bad = bytes.clone();
+        bad[0] = b'X';
+        assert_eq!(FrozenSrbTreeMap::new(&bad).unwrap_err(), FrozenError::Magic);
+        let mut bad = bytes.clone();
+        bad[4] = 2;
+        assert_eq!(
+            FrozenSrbTreeMap::new(&bad).unwrap_err(),
+            FrozenError::Version { found: 2 }
+        );
+
+        // flipping any bit either fails validation or still reads in bounds
+        for idx in 6..bytes.len() {
+            for bit in 0..8 {
+                let mut bad = bytes.clone();
+                bad[idx] ^= 1 << bit;
+                if let Ok(frozen) = FrozenSrbTreeMap::new(&bad) {
+                    assert_eq!(frozen.iter().count(), frozen.len(), "{idx}");
+                    for (key, value) in frozen.iter() {
+                        assert_eq!(frozen.get(key), Some(value));
+                    }
+                }
+            }
+        }
+    }
+}
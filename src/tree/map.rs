@@ -1,67 +1,547 @@
-use std::{borrow::Borrow, fmt::Debug};
+use std::cmp::Ordering;
+use std::ops::{Bound, RangeBounds};
+use std::{borrow::Borrow, fmt::Debug, slice};
+
+use allocator_api2::alloc::{Allocator, Global};
+#[cfg(feature = "rayon")]
+use rayon::iter::{IntoParallelIterator, ParallelExtend, ParallelIterator};
 
 use crate::key::Key;
 
-use super::node::Node;
-use super::B;
+use super::chunks::{self, Chunk};
+use super::error::{BuildError, InsertError};
+use super::frozen;
+use super::node;
+use super::node::bulk::Builder;
+use super::node::pool::Pool;
+#[cfg(feature = "rayon")]
+use super::par::{ParIter, ParIterMut};
+use super::storage::{Inline, Storage, StorageMode, Trie, DEMOTE, INLINE};
+use super::{TreeStats, B};
 
-pub struct SrbTreeMap<K, V> {
-    root: Node<K, V, B>,
+/// An ordered map over integer keys stored in a radix trie.
+///
+/// The map owns all of its nodes, so it is `Send` and `Sync` whenever its keys, values and
+/// allocator are. Shared references only read, any number of threads can look up and iterate at
+/// once.
+pub struct SrbTreeMap<K, V, A: Allocator + Clone = Global> {
+    storage: Storage<K, V, A>,
+    /// The largest key this tree accepts, if it was bounded on construction.
+    max_key: Option<K>,
+    /// The allocator nodes are allocated in once the tree is promoted to a trie.
+    alloc: A,
+    pool: Pool<K, V, B, A>,
 }
 
 impl<K, V> SrbTreeMap<K, V> {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
+        Self::new_in(Global)
+    }
+
+    /// Creates a tree which only accepts keys up to and including `max_key`.
+    ///
+    /// The height of the tree never exceeds the height needed for `max_key`, inserting larger keys
+    /// fails with [`InsertError::OutOfRange`].
+    pub const fn with_universe(max_key: K) -> Self {
+        Self::with_universe_in(max_key, Global)
+    }
+}
+
+impl<K, V, A: Allocator + Clone> SrbTreeMap<K, V, A> {
+    /// Creates a tree which allocates its nodes in the given allocator.
+    pub const fn new_in(alloc: A) -> Self {
+        Self {
+            storage: Storage::Inline(Inline::new()),
+            max_key: None,
+            alloc,
+            pool: Pool::new(),
+        }
+    }
+
+    /// Creates a tree in the given allocator which only accepts keys up to and including
+    /// `max_key`, see [`Self::with_universe`].
+    pub const fn with_universe_in(max_key: K, alloc: A) -> Self {
         Self {
-            root: Node::new_internal(),
+            storage: Storage::Inline(Inline::new()),
+            max_key: Some(max_key),
+            alloc,
+            pool: Pool::new(),
+        }
+    }
+
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Returns the largest key the tree accepts if it was created with [`Self::with_universe`].
+    pub fn max_key(&self) -> Option<&K> {
+        self.max_key.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all entries, emptied nodes are kept for reuse up to the pool limit.
+    pub fn clear(&mut self) {
+        let old = std::mem::replace(&mut self.storage, Storage::Inline(Inline::new()));
+        if let Storage::Trie(trie) = old {
+            self.pool.recycle(trie.root);
+        }
+    }
+
+    /// Sets the number of emptied nodes [`Self::clear`] and removals keep around for later
+    /// inserts, this is zero by default.
+    pub fn set_pool_limit(&mut self, limit: usize) {
+        self.pool.set_limit(limit);
+    }
+
+    /// Returns the number of emptied nodes kept around for later inserts and the number of heap
+    /// bytes they hold.
+    pub fn pool_size(&self) -> (usize, usize) {
+        (self.pool.len(), self.pool.heap_bytes())
+    }
+
+    /// Releases all nodes kept around for later inserts, the pool limit is kept.
+    pub fn shrink_pool(&mut self) {
+        self.pool.shrink();
+    }
+
+    /// Returns the mode the entries are currently stored in and the number of heap bytes allocated
+    /// for it, inline storage doesn't allocate.
+    pub fn storage_bytes(&self) -> (StorageMode, usize) {
+        let bytes = match &self.storage {
+            Storage::Inline(_) => 0,
+            Storage::Trie(trie) => trie.root.storage_bytes(),
+        };
+
+        (self.storage.mode(), bytes)
+    }
+
+    /// Returns a report on the shape and memory use of this tree.
+    ///
+    /// This walks the whole tree.
+    pub fn stats(&self) -> TreeStats {
+        let (mode, heap_bytes) = self.storage_bytes();
+        let mut stats = TreeStats::new(mode, self.len());
+        stats.heap_bytes = heap_bytes;
+
+        if let Storage::Trie(trie) = &self.storage {
+            trie.root.collect_stats(0, &mut stats);
+        }
+
+        stats
+    }
+
+    pub fn storage(&self) -> f32 {
+        let (total, used) = match &self.storage {
+            Storage::Inline(inline) => (INLINE, inline.len()),
+            Storage::Trie(trie) => trie.root.storage_util(),
+        };
+
+        used as f32 / total as f32
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V, A> {
+        Iter {
+            inner: match &self.storage {
+                Storage::Inline(inline) => IterInner::Inline(inline.entries()),
+                Storage::Trie(trie) => IterInner::Trie(trie.root.pairs()),
+            },
+        }
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V, A> {
+        Keys {
+            inner: match &self.storage {
+                Storage::Inline(inline) => IterInner::Inline(inline.entries()),
+                Storage::Trie(trie) => IterInner::Trie(trie.root.keys()),
+            },
+        }
+    }
+
+    pub fn values(&self) -> Values<'_, K, V, A> {
+        Values {
+            inner: match &self.storage {
+                Storage::Inline(inline) => IterInner::Inline(inline.entries()),
+                Storage::Trie(trie) => IterInner::Trie(trie.root.values()),
+            },
+        }
+    }
+
+    /// Splits the entries into `n` consecutive chunks with about the same number of entries.
+    ///
+    /// Chunks are made of whole subtrees which are found through the entry counts of the nodes
+    /// above them, so no leaf is visited. A chunk can be off by up to half a leaf, and chunks
+    /// are empty if there are fewer entries than chunks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn chunks(&self, n: usize) -> Vec<Chunk<'_, K, V, A>> {
+        chunks::split(&self.storage, n)
+    }
+
+    pub fn first(&self) -> Option<(&K, &V)> {
+        match &self.storage {
+            Storage::Inline(inline) => inline.first(),
+            Storage::Trie(trie) => trie.root.first(),
+        }
+    }
+
+    pub fn first_mut(&mut self) -> Option<(&K, &mut V)> {
+        match &mut self.storage {
+            Storage::Inline(inline) => inline.first_mut(),
+            Storage::Trie(trie) => trie.root.first_mut().map(|(k, v)| (&*k, v)),
+        }
+    }
+
+    pub fn last(&self) -> Option<(&K, &V)> {
+        match &self.storage {
+            Storage::Inline(inline) => inline.last(),
+            Storage::Trie(trie) => trie.root.last(),
+        }
+    }
+
+    pub fn last_mut(&mut self) -> Option<(&K, &mut V)> {
+        match &mut self.storage {
+            Storage::Inline(inline) => inline.last_mut(),
+            Storage::Trie(trie) => trie.root.last_mut().map(|(k, v)| (&*k, v)),
         }
     }
 }
 
+impl<K, V> Default for SrbTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<K: Key, V> SrbTreeMap<K, V> {
+    /// Creates a tree from entries with strictly ascending keys, see [`Self::from_sorted_iter_in`].
+    pub fn from_sorted_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Result<Self, BuildError> {
+        Self::from_sorted_iter_in(iter, Global)
+    }
+}
+
+impl<K: Key, V, A: Allocator + Clone> SrbTreeMap<K, V, A> {
+    /// Creates a tree in the given allocator from entries with strictly ascending keys.
+    ///
+    /// Unlike collecting into a tree this builds the nodes from left to right, creating each of
+    /// them exactly once. Keys which are out of order or repeated are reported by their position.
+    pub fn from_sorted_iter_in<I>(iter: I, alloc: A) -> Result<Self, BuildError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut inline = Inline::<K, V>::new();
+        let mut builder = Builder::new(alloc.clone());
+
+        for (index, (key, value)) in iter.into_iter().enumerate() {
+            let last = builder.last().or_else(|| inline.last().map(|(k, _)| k));
+            match last.map(|last| last.cmp(&key)) {
+                Some(Ordering::Equal) => return Err(BuildError::Duplicate { index }),
+                Some(Ordering::Greater) => return Err(BuildError::Unsorted { index }),
+                _ => {}
+            }
+
+            let (key, value) = match builder.last() {
+                Some(_) => (key, value),
+                None => match inline.insert(key, value) {
+                    Ok(_) => continue,
+                    Err(entry) => {
+                        // too many entries to store inline, they end up in a trie after all
+                        for (key, value) in inline.drain() {
+                            builder.push(key, value);
+                        }
+                        entry
+                    }
+                },
+            };
+            builder.push(key, value);
+        }
+
+        let mut this = Self::new_in(alloc);
+        if let Some((root, height)) = builder.finish() {
+            this.storage = Storage::Trie(Trie { root, height });
+        } else {
+            this.storage = Storage::Inline(inline);
+        }
+
+        Ok(this)
+    }
+
+    fn contains_range(&self, key: &K) -> bool {
+        self.max_key.as_ref().is_none_or(|max| key <= max)
+    }
+
+    /// Inserts a key value pair, returning the previous pair for this key.
+    ///
+    /// # Panics
+    /// Panics if the key is outside of the tree's universe, see [`Self::try_insert`].
     pub fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
-        self.root.insert(0, key, value)
+        if !self.contains_range(&key) {
+            panic!("{}", InsertError::OutOfRange);
+        }
+
+        match &mut self.storage {
+            Storage::Inline(inline) => match inline.insert(key, value) {
+                Ok(old) => old,
+                Err((key, value)) => {
+                    let mut trie = Trie::promote(inline, self.alloc.clone(), &mut self.pool);
+                    let old = trie.insert(key, value, &mut self.pool);
+                    self.storage = Storage::Trie(trie);
+                    old
+                }
+            },
+            Storage::Trie(trie) => trie.insert(key, value, &mut self.pool),
+        }
+    }
+
+    /// Inserts all pairs of `iter`, returning how many of their keys were not in the tree before.
+    ///
+    /// Each pair resumes from the deepest node it shares with the pair before it instead of
+    /// descending from the root, so sorted or clustered keys only touch the last levels of the
+    /// trie. Pairs with equal keys replace each other just like with [`Self::insert`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if a key is outside of the tree's universe, the pairs before it are inserted.
+    pub fn insert_batch<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) -> usize {
+        let len = self.len();
+        let mut batch = iter.into_iter().peekable();
+
+        while batch.peek().is_some() {
+            if let Storage::Trie(trie) = &mut self.storage {
+                let max_key = &self.max_key;
+                let accept = |key: &K| max_key.as_ref().is_none_or(|max| key <= max);
+                trie.insert_run(&mut batch, &accept, &mut self.pool);
+            }
+
+            // inline storage and keys which need a taller trie or are out of range take the
+            // regular path
+            if let Some((key, value)) = batch.next() {
+                self.insert(key, value);
+            }
+        }
+
+        self.len() - len
+    }
+
+    /// Inserts pairs with ascending keys, see [`Self::insert_batch`].
+    ///
+    /// Consecutive keys of sorted input share all but their last digits most of the time, so this
+    /// only descends a level or two for most of them.
+    pub fn extend_sorted<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.insert_batch(iter);
+    }
+
+    /// Inserts a key value pair, returning the previous pair for this key or an error if the key
+    /// is outside of the tree's universe or a node could not be allocated.
+    ///
+    /// The tree holds the same entries as before if this fails.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<(K, V)>, InsertError> {
+        self.try_reserve_path(&key)?;
+        Ok(self.insert(key, value))
+    }
+
+    /// Allocates everything needed to insert the given key, so that inserting it afterwards
+    /// doesn't allocate.
+    ///
+    /// The tree holds the same entries as before if this fails.
+    pub fn try_reserve_path(&mut self, key: &K) -> Result<(), InsertError> {
+        if !self.contains_range(key) {
+            return Err(InsertError::OutOfRange);
+        }
+
+        let promoted = match &mut self.storage {
+            Storage::Inline(inline) if inline.len() == INLINE && inline.get(key).is_none() => {
+                self.storage = Storage::Trie(Trie::try_promote(
+                    inline,
+                    self.alloc.clone(),
+                    &mut self.pool,
+                )?);
+                true
+            }
+            Storage::Inline(_) => return Ok(()),
+            Storage::Trie(_) => false,
+        };
+
+        let Storage::Trie(trie) = &mut self.storage else {
+            unreachable!("inline storage is handled above");
+        };
+
+        if let Err(err) = trie.try_reserve_path(key) {
+            if promoted {
+                self.storage.demote(&mut self.pool);
+            }
+
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+
+    /// Allocates all nodes holding the keys in the given range, so that inserting them afterwards
+    /// doesn't allocate.
+    ///
+    /// This switches the tree to trie storage right away and keeps the nodes until entries are
+    /// removed again, keys outside of the tree's universe are ignored. Once removals bring the
+    /// tree back to inline storage, the reserved nodes are only kept up to the pool limit, see
+    /// [`Self::set_pool_limit`].
+    pub fn reserve_range<R: RangeBounds<K>>(&mut self, range: R) {
+        let lo = match range.start_bound() {
+            Bound::Included(start) => start.to_usize(),
+            Bound::Excluded(start) => match start.to_usize().checked_add(1) {
+                Some(lo) => lo,
+                None => return,
+            },
+            Bound::Unbounded => K::MIN.to_usize(),
+        };
+        let hi = match range.end_bound() {
+            Bound::Included(end) => end.to_usize(),
+            Bound::Excluded(end) => match end.to_usize().checked_sub(1) {
+                Some(hi) => hi,
+                None => return,
+            },
+            Bound::Unbounded => K::MAX.to_usize(),
+        };
+        let hi = self
+            .max_key
+            .as_ref()
+            .map_or(hi, |max| hi.min(max.to_usize()));
+        if lo > hi {
+            return;
+        }
+
+        if let Storage::Inline(inline) = &mut self.storage {
+            let trie = Trie::promote(inline, self.alloc.clone(), &mut self.pool);
+            self.storage = Storage::Trie(trie);
+        }
+
+        if let Storage::Trie(trie) = &mut self.storage {
+            trie.reserve_range(lo, hi, &mut self.pool);
+        }
     }
 
     pub fn remove<Q: Borrow<K>>(&mut self, key: &Q) -> Option<(K, V)> {
-        self.root.remove(0, key.borrow())
+        let key = key.borrow();
+        if !self.contains_range(key) {
+            return None;
+        }
+
+        match &mut self.storage {
+            Storage::Inline(inline) => inline.remove(key),
+            Storage::Trie(trie) => {
+                let old = trie.remove(key, &mut self.pool);
+                if old.is_some() && trie.len() <= DEMOTE {
+                    self.storage.demote(&mut self.pool);
+                }
+
+                old
+            }
+        }
     }
 
     pub fn get<Q: Borrow<K>>(&self, key: &Q) -> Option<(&K, &V)> {
-        self.root.get(0, key.borrow())
+        let key = key.borrow();
+        if !self.contains_range(key) {
+            return None;
+        }
+
+        match &self.storage {
+            Storage::Inline(inline) => inline.get(key),
+            Storage::Trie(trie) => trie.get(key),
+        }
     }
 
     pub fn get_mut<Q: Borrow<K>>(&mut self, key: &Q) -> Option<(&K, &mut V)> {
-        self.root.get_mut(0, key.borrow()).map(|(k, v)| (&*k, v))
+        let key = key.borrow();
+        if !self.contains_range(key) {
+            return None;
+        }
+
+        match &mut self.storage {
+            Storage::Inline(inline) => inline.get_mut(key),
+            Storage::Trie(trie) => trie.get_mut(key).map(|(k, v)| (&*k, v)),
+        }
     }
 
-    pub fn first(&self) -> Option<(&K, &V)> {
-        self.root.first()
+    /// Writes the tree into the format read in place by [`FrozenSrbTreeMap`], see
+    /// [`SrbTreeSet::freeze`]. Each value is stored as the bytes it references.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a key doesn't fit into a `u64` or the frozen tree doesn't fit into 4 GiB.
+    ///
+    /// [`FrozenSrbTreeMap`]: super::frozen::FrozenSrbTreeMap
+    /// [`SrbTreeSet::freeze`]: super::set::SrbTreeSet::freeze
+    pub fn freeze(&self) -> Vec<u8>
+    where
+        V: AsRef<[u8]>,
+    {
+        let entries: Vec<_> = self
+            .iter()
+            .map(|(k, v)| (frozen::key(k), v.as_ref()))
+            .collect();
+        frozen::freeze(&entries, frozen::Kind::Map)
     }
+}
 
-    pub fn first_mut(&mut self) -> Option<(&K, &mut V)> {
-        self.root.first_mut().map(|(k, v)| (&*k, v))
+#[cfg(feature = "rayon")]
+impl<K, V, A: Allocator + Clone> SrbTreeMap<K, V, A> {
+    /// Returns a parallel iterator over the entries.
+    ///
+    /// Work is split along the children of the root and further down, at the child where half of
+    /// the remaining entries are on either side.
+    pub fn par_iter(&self) -> ParIter<'_, K, V, A>
+    where
+        K: Sync,
+        V: Sync,
+        A: Sync,
+    {
+        ParIter::new(&self.storage)
     }
 
-    pub fn last(&self) -> Option<(&K, &V)> {
-        self.root.last()
+    /// Returns a parallel iterator over the entries with mutable values, see [`Self::par_iter`].
+    pub fn par_iter_mut(&mut self) -> ParIterMut<'_, K, V, A>
+    where
+        K: Send + Sync,
+        V: Send,
+        A: Send,
+    {
+        ParIterMut::new(&mut self.storage)
     }
 
-    pub fn last_mut(&mut self) -> Option<(&K, &mut V)> {
-        self.root.last_mut().map(|(k, v)| (&*k, v))
+    pub fn par_keys(&self) -> impl ParallelIterator<Item = &K> + '_
+    where
+        K: Sync,
+        V: Sync,
+        A: Sync,
+    {
+        self.par_iter().map(|(k, _)| k)
+    }
+
+    pub fn par_values(&self) -> impl ParallelIterator<Item = &V> + '_
+    where
+        K: Sync,
+        V: Sync,
+        A: Sync,
+    {
+        self.par_iter().map(|(_, v)| v)
     }
 }
 
-impl<K: Debug, V: Debug> Debug for SrbTreeMap<K, V> {
+impl<K: Debug, V: Debug, A: Allocator + Clone> Debug for SrbTreeMap<K, V, A> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut map = f.debug_map();
-        self.root.pairs().for_each(|(k, v)| {
-            map.entry(k, v);
-        });
-        map.finish()
+        f.debug_map().entries(self.iter()).finish()
     }
 }
 
-impl<K: Key, V> Extend<(K, V)> for SrbTreeMap<K, V> {
+impl<K: Key, V, A: Allocator + Clone> Extend<(K, V)> for SrbTreeMap<K, V, A> {
     fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
         for (k, v) in iter {
             self.insert(k, v);
@@ -69,6 +549,42 @@ impl<K: Key, V> Extend<(K, V)> for SrbTreeMap<K, V> {
     }
 }
 
+#[cfg(feature = "rayon")]
+impl<K: Key + Send, V: Send, A: Allocator + Clone + Send> ParallelExtend<(K, V)>
+    for SrbTreeMap<K, V, A>
+{
+    /// Inserts all pairs, the pairs are partitioned by their digit below the root and the subtree
+    /// of every child is built by a task of its own.
+    ///
+    /// Pairs with equal keys replace each other in the order of the iterator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a key is outside of the tree's universe, no pair is inserted then.
+    fn par_extend<I: IntoParallelIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let entries: Vec<(K, V)> = iter.into_par_iter().collect();
+        if entries.iter().any(|(key, _)| !self.contains_range(key)) {
+            panic!("{}", InsertError::OutOfRange);
+        }
+
+        if let Storage::Inline(inline) = &mut self.storage {
+            if inline.len() + entries.len() <= INLINE {
+                for (key, value) in entries {
+                    self.insert(key, value);
+                }
+                return;
+            }
+
+            let trie = Trie::promote(inline, self.alloc.clone(), &mut self.pool);
+            self.storage = Storage::Trie(trie);
+        }
+
+        if let Storage::Trie(trie) = &mut self.storage {
+            trie.par_extend(entries, &mut self.pool);
+        }
+    }
+}
+
 impl<K: Key, V> FromIterator<(K, V)> for SrbTreeMap<K, V> {
     fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
         let mut this = Self::new();
@@ -76,3 +592,559 @@ impl<K: Key, V> FromIterator<(K, V)> for SrbTreeMap<K, V> {
         this
     }
 }
+
+#[derive(Debug)]
+enum IterInner<'a, K, V, T> {
+    Inline(slice::Iter<'a, Option<(K, V)>>),
+    Trie(T),
+}
+
+impl<'a, K, V, T> IterInner<'a, K, V, T> {
+    fn next_with<U>(
+        &mut self,
+        inline: impl FnOnce(&'a K, &'a V) -> U,
+        trie: impl FnOnce(&mut T) -> Option<U>,
+    ) -> Option<U> {
+        match self {
+            IterInner::Inline(iter) => iter.next()?.as_ref().map(|(k, v)| inline(k, v)),
+            IterInner::Trie(iter) => trie(iter),
+        }
+    }
+
+    fn next_back_with<U>(
+        &mut self,
+        inline: impl FnOnce(&'a K, &'a V) -> U,
+        trie: impl FnOnce(&mut T) -> Option<U>,
+    ) -> Option<U> {
+        match self {
+            IterInner::Inline(iter) => iter.next_back()?.as_ref().map(|(k, v)| inline(k, v)),
+            IterInner::Trie(iter) => trie(iter),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Iter<'a, K, V, A: Allocator + Clone = Global> {
+    inner: IterInner<'a, K, V, node::Pairs<'a, K, V, B, A>>,
+}
+
+impl<'a, K, V, A: Allocator + Clone> Iterator for Iter<'a, K, V, A> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next_with(|k, v| (k, v), Iterator::next)
+    }
+}
+
+impl<'a, K, V, A: Allocator + Clone> DoubleEndedIterator for Iter<'a, K, V, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next_back_with(|k, v| (k, v), DoubleEndedIterator::next_back)
+    }
+}
+
+#[derive(Debug)]
+pub struct Keys<'a, K, V, A: Allocator + Clone = Global> {
+    inner: IterInner<'a, K, V, node::Keys<'a, K, V, B, A>>,
+}
+
+impl<'a, K, V, A: Allocator + Clone> Iterator for Keys<'a, K, V, A> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next_with(|k, _| k, Iterator::next)
+    }
+}
+
+impl<'a, K, V, A: Allocator + Clone> DoubleEndedIterator for Keys<'a, K, V, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next_back_with(|k, _| k, DoubleEndedIterator::next_back)
+    }
+}
+
+#[derive(Debug)]
+pub struct Values<'a, K, V, A: Allocator + Clone = Global> {
+    inner: IterInner<'a, K, V, node::Values<'a, K, V, B, A>>,
+}
+
+impl<'a, K, V, A: Allocator + Clone> Iterator for Values<'a, K, V, A> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next_with(|_, v| v, Iterator::next)
+    }
+}
+
+impl<'a, K, V, A: Allocator + Clone> DoubleEndedIterator for Values<'a, K, V, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next_back_with(|_, v| v, DoubleEndedIterator::next_back)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::Layout;
+    use std::cell::Cell;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::ptr::NonNull;
+    use std::rc::Rc;
+
+    use allocator_api2::alloc::AllocError;
+
+    use super::*;
+    use crate::tree::set::SrbTreeSet;
+
+    /// Counts the live allocations made through it and fails once its budget is used up.
+    #[derive(Debug, Clone)]
+    struct Counting {
+        live: Rc<Cell<usize>>,
+        budget: Rc<Cell<usize>>,
+    }
+
+    impl Default for Counting {
+        fn default() -> Self {
+            Self {
+                live: Rc::default(),
+                budget: Rc::new(Cell::new(usize::MAX)),
+            }
+        }
+    }
+
+    unsafe impl Allocator for Counting {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            let budget = self.budget.get().checked_sub(1).ok_or(AllocError)?;
+            self.budget.set(budget);
+            self.live.set(self.live.get() + 1);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.live.set(self.live.get() - 1);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    fn height<K, V>(map: &SrbTreeMap<K, V>) -> Option<usize> {
+        match &map.storage {
+            Storage::Inline(_) => None,
+            Storage::Trie(trie) => Some(trie.height),
+        }
+    }
+
+    #[test]
+    fn test_height() {
+        let mut map: SrbTreeMap<u64, ()> = (0..10).map(|k| (k, ())).collect();
+        assert_eq!(height(&map), Some(0));
+
+        map.insert(1000, ());
+        assert_eq!(height(&map), Some(2));
+        assert_eq!(map.get(&7), Some((&7, &())));
+        assert_eq!(map.get(&1000), Some((&1000, &())));
+        assert_eq!(map.get(&100_000), None);
+
+        map.remove(&1000);
+        assert_eq!(height(&map), Some(0));
+        assert_eq!(
+            map.keys().copied().collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_small_keys_stay_shallow() {
+        let map: SrbTreeMap<u64, ()> = (0..1000).map(|k| (k, ())).collect();
+        assert_eq!(height(&map), Some(2));
+        assert_eq!(map.keys().count(), 1000);
+    }
+
+    #[test]
+    fn test_universe() {
+        let mut map: SrbTreeMap<u32, ()> = SrbTreeMap::with_universe(1 << 20);
+
+        assert_eq!(map.try_insert(1 << 20, ()), Ok(None));
+        assert_eq!(
+            map.try_insert((1 << 20) + 1, ()),
+            Err(InsertError::OutOfRange)
+        );
+        assert_eq!(map.try_insert(u32::MAX, ()), Err(InsertError::OutOfRange));
+        assert_eq!(map.get(&((1 << 20) + 1)), None);
+        assert_eq!(map.max_key(), Some(&(1 << 20)));
+
+        // the bound of the universe, not the largest value held
+        let mut set = SrbTreeSet::with_universe(1000u32);
+        set.insert(7);
+        assert_eq!((set.max_key(), set.last()), (Some(&1000), Some(&7)));
+    }
+
+    #[test]
+    fn test_modes() {
+        const EMPTY: SrbTreeMap<u32, u32> = SrbTreeMap::new();
+
+        let mut map = EMPTY;
+        assert_eq!(map.storage_bytes().0, StorageMode::Inline);
+        assert_eq!(map.iter().next(), None);
+        assert_eq!(map.remove(&3), None);
+
+        for key in (0..INLINE as u32).rev() {
+            map.insert(key * 100, key);
+        }
+        assert_eq!(map.storage_bytes().0, StorageMode::Inline);
+
+        map.insert(50, 0);
+        assert_eq!(map.storage_bytes().0, StorageMode::Trie);
+
+        // stays a trie until it falls well below the inline capacity
+        for key in 0..(INLINE - DEMOTE) as u32 {
+            map.remove(&(key * 100));
+            assert_eq!(map.storage_bytes().0, StorageMode::Trie);
+        }
+
+        map.remove(&50);
+        assert_eq!(map.storage_bytes().0, StorageMode::Inline);
+        assert_eq!(map.len(), DEMOTE);
+
+        let expected: Vec<_> = ((INLINE - DEMOTE) as u32..INLINE as u32)
+            .map(|key| (key * 100, key))
+            .collect();
+        assert_eq!(
+            map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(
+            map.iter().rev().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+            expected.into_iter().rev().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        use std::mem::size_of;
+
+        use super::super::node::{children::Full, slots::Slots};
+
+        let mut map: SrbTreeMap<u32, ()> = SrbTreeMap::new();
+        map.insert(3, ());
+        let stats = map.stats();
+        assert_eq!(stats.mode, StorageMode::Inline);
+        assert_eq!(stats.heap_bytes, 0);
+        assert_eq!(stats.nodes(), 0);
+
+        map.extend((0..100).map(|k| (k, ())));
+        let stats = map.stats();
+        assert_eq!(stats.mode, StorageMode::Trie);
+        assert_eq!(stats.len, 100);
+        assert_eq!(stats.nodes_per_depth, [1, 7]);
+        assert_eq!(stats.leaf_fill[16], 6);
+        assert_eq!(stats.leaf_fill[4], 1);
+        assert_eq!(stats.leafs(), 7);
+        assert_eq!(stats.stumps, 0);
+
+        // a full root, six dense leafs and one sparse leaf with 4 entries
+        let expected = size_of::<Full<u32, (), B, Global>>()
+            + 6 * size_of::<Slots<u32, (), B>>()
+            + 4 * size_of::<(u32, ())>();
+        assert_eq!(stats.heap_bytes, expected);
+        assert_eq!(stats.bytes_per_entry(), expected as f64 / 100.0);
+    }
+
+    #[test]
+    fn test_allocator() {
+        let alloc = Counting::default();
+        let mut map = SrbTreeMap::new_in(alloc.clone());
+
+        map.extend((0..INLINE as u32).map(|k| (k * 7, k)));
+        assert_eq!(alloc.live.get(), 0);
+
+        map.extend((0..1000).map(|k| (k * 7, k)));
+        assert_ne!(alloc.live.get(), 0);
+        assert_eq!(map.get(&700), Some((&700, &100)));
+
+        for key in 0..990 {
+            map.remove(&(key * 7));
+        }
+        assert_eq!(map.len(), 10);
+
+        drop(map);
+        assert_eq!(alloc.live.get(), 0);
+    }
+
+    #[test]
+    fn test_try_insert_alloc_failure() {
+        let alloc = Counting::default();
+        let mut map = SrbTreeMap::new_in(alloc.clone());
+        let mut expected = BTreeMap::new();
+
+        for key in 0..INLINE as u32 {
+            map.insert(key * 1000, key);
+            expected.insert(key * 1000, key);
+        }
+
+        alloc.budget.set(0);
+        assert_eq!(map.try_insert(1, 1), Err(InsertError::Alloc));
+        assert_eq!(map.try_insert(0, 1), Ok(Some((0, 0))));
+        assert_eq!(map.storage_bytes(), (StorageMode::Inline, 0));
+        map.insert(0, 0);
+
+        for (idx, key) in (0..2000u32)
+            .map(|k| k.wrapping_mul(2_654_435_761) % (1 << 20))
+            .enumerate()
+        {
+            alloc.budget.set(idx % 4);
+            match map.try_insert(key, idx as u32) {
+                Ok(old) => {
+                    assert_eq!(old.map(|(_, v)| v), expected.insert(key, idx as u32));
+                }
+                Err(err) => assert_eq!(err, InsertError::Alloc),
+            }
+
+            assert_eq!(map.len(), expected.len());
+            assert!(map.iter().eq(expected.iter()));
+        }
+
+        assert_eq!(map.stats().stumps, 0);
+        alloc.budget.set(usize::MAX);
+        assert_eq!(map.try_insert(7, 7), Ok(None));
+    }
+
+    #[test]
+    fn test_clear_pool() {
+        let alloc = Counting::default();
+        let allocations = || usize::MAX - alloc.budget.get();
+        let entries = || (0..1000).map(|k| (k * 7, k));
+
+        let mut map = SrbTreeMap::new_in(alloc.clone());
+        map.extend(entries());
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.iter().next(), None);
+        assert_eq!(alloc.live.get(), 0);
+
+        map.set_pool_limit(usize::MAX);
+        let before = allocations();
+        map.extend(entries());
+        let fresh = allocations() - before;
+        let live = alloc.live.get();
+
+        map.clear();
+        assert_eq!(map.storage_bytes(), (StorageMode::Inline, 0));
+        assert_eq!(alloc.live.get(), live);
+        assert_ne!(map.pool_size().0, 0);
+
+        let before = allocations();
+        map.extend(entries());
+        let reused = allocations() - before;
+        assert!(map.iter().map(|(k, v)| (*k, *v)).eq(entries()));
+        assert!(reused < fresh / 2, "{reused} of {fresh} allocations");
+
+        map.clear();
+        map.shrink_pool();
+        assert_eq!(map.pool_size(), (0, 0));
+        assert_eq!(alloc.live.get(), 0);
+
+        // levels removed from above the root go to the pool as well
+        map.extend(entries());
+        map.insert(1 << 20, 0);
+        map.remove(&(1 << 20));
+        assert_eq!(map.pool_size().0, 2);
+    }
+
+    #[test]
+    fn test_from_sorted_iter() {
+        let alloc = Counting::default();
+        let allocations = || usize::MAX - alloc.budget.get();
+        let inputs: [Vec<u64>; 5] = [
+            vec![],
+            (0..8).collect(),
+            (0..9).collect(),
+            (0..5000).map(|k| k * k).collect(),
+            (0..300).chain(70_000..70_100).chain([u64::MAX]).collect(),
+        ];
+
+        for keys in inputs {
+            let mut inserted = SrbTreeMap::new_in(Counting::default());
+            inserted.extend(keys.iter().map(|k| (*k, *k)));
+
+            let before = allocations();
+            let built =
+                SrbTreeMap::from_sorted_iter_in(keys.iter().map(|k| (*k, *k)), alloc.clone())
+                    .unwrap();
+            assert!(built.iter().eq(inserted.iter()));
+            assert_eq!(built.stats(), inserted.stats());
+            assert_eq!(allocations() - before, built.stats().nodes());
+        }
+
+        let keys = [1u32, 4, 4, 2];
+        let err = SrbTreeMap::from_sorted_iter(keys.map(|k| (k, ()))).unwrap_err();
+        assert_eq!(err, BuildError::Duplicate { index: 2 });
+
+        let keys = (0..100u32).chain([50]);
+        let err = SrbTreeSet::from_sorted_iter(keys).unwrap_err();
+        assert_eq!(err, BuildError::Unsorted { index: 100 });
+        assert_eq!(alloc.live.get(), 0);
+    }
+
+    #[test]
+    fn test_insert_batch() {
+        // runs of nearby keys jumping around, with repeats and keys needing a taller trie
+        let mut seed = 7u64;
+        let mut keys = Vec::new();
+        for run in 0..200u64 {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let start = (seed >> 40) % (64 << (run / 20));
+            keys.extend((0..seed % 50).map(|k| start + k * (seed % 3 + 1)));
+        }
+
+        let mut map = SrbTreeMap::new();
+        let mut model = BTreeMap::new();
+        for (batch, keys) in keys.chunks(500).enumerate() {
+            let pairs = || keys.iter().map(|k| (*k, batch));
+            let new: BTreeSet<_> = keys.iter().filter(|k| !model.contains_key(*k)).collect();
+
+            model.extend(pairs());
+            assert_eq!(map.insert_batch(pairs()), new.len());
+            assert_eq!(map.len(), model.len());
+        }
+        assert!(map.iter().map(|(k, v)| (*k, *v)).eq(model.into_iter()));
+
+        let mut set = SrbTreeSet::new();
+        set.extend_sorted(0..5000u32);
+        set.extend_sorted(4000..10_000);
+        assert_eq!(set.len(), 10_000);
+        assert!(set.iter().copied().eq(0..10_000));
+        assert_eq!(set.stats(), (0..10_000).collect::<SrbTreeSet<_>>().stats());
+
+        let mut map = SrbTreeMap::with_universe(100u32);
+        let result = std::panic::catch_unwind(move || map.insert_batch([(5, ()), (101, ())]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_reserve_range() {
+        let alloc = Counting::default();
+        let allocations = || usize::MAX - alloc.budget.get();
+
+        let mut map = SrbTreeMap::new_in(alloc.clone());
+        map.insert(3u32, 3);
+        map.reserve_range(1000..5000);
+        assert_eq!(map.storage_bytes().0, StorageMode::Trie);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&1000), None);
+
+        // removing absent keys keeps the reserved nodes around
+        assert_eq!(map.remove(&1000), None);
+
+        let before = allocations();
+        for key in (1000..5000).rev() {
+            map.insert(key, key);
+        }
+        assert_eq!(allocations(), before);
+        assert!(map.keys().copied().eq([3].into_iter().chain(1000..5000)));
+
+        map.insert(6000, 6000);
+        assert_ne!(allocations(), before);
+
+        // reserved nodes go to the pool when removals switch back to inline storage
+        let pooled = Counting::default();
+        let mut map = SrbTreeMap::new_in(pooled.clone());
+        map.set_pool_limit(usize::MAX);
+        map.insert(3u32, 3);
+        map.reserve_range(0x1000..0x2000);
+        let live = pooled.live.get();
+        assert_eq!(map.remove(&3), Some((3, 3)));
+        assert_eq!(map.storage_bytes(), (StorageMode::Inline, 0));
+        assert_eq!(pooled.live.get(), map.pool_size().0);
+        // only the path of the removed key is freed
+        assert!(pooled.live.get() + 3 >= live, "{live}");
+
+        let before = pooled.budget.get();
+        map.extend((0x1000..0x2000).map(|k| (k, k)));
+        let allocated = before - pooled.budget.get();
+        assert!(allocated < live / 20, "{allocated} of {live} allocations");
+
+        // growing the trie keeps reserved nodes
+        let mut map = SrbTreeMap::new_in(alloc.clone());
+        map.reserve_range(0x100..0x200);
+        map.insert(0x10000, 0);
+        let before = allocations();
+        map.extend((0x100..0x200).map(|k| (k, k)));
+        assert_eq!(allocations(), before);
+
+        // so do removals lowering it
+        let mut map = SrbTreeMap::new_in(alloc.clone());
+        map.extend((0..INLINE as u32).map(|k| (k, k)));
+        map.reserve_range(0x100..0x200);
+        map.insert(0x10000, 0);
+        assert_eq!(map.remove(&0x10000), Some((0x10000, 0)));
+        let before = allocations();
+        map.extend((0x100..0x200).map(|k| (k, k)));
+        assert_eq!(allocations(), before);
+
+        // failed inserts above the reserved range keep its nodes and height
+        let mut map = SrbTreeMap::new_in(alloc.clone());
+        map.insert(3u32, 3);
+        map.reserve_range(0x100..0x200);
+        let bytes = map.storage_bytes();
+        for budget in 0..4 {
+            alloc.budget.set(budget);
+            assert_eq!(map.try_insert(0x10000, 0), Err(InsertError::Alloc));
+            assert_eq!(map.storage_bytes(), bytes);
+        }
+        alloc.budget.set(usize::MAX);
+        let before = allocations();
+        map.extend((0x100..0x200).map(|k| (k, k)));
+        assert_eq!(allocations(), before);
+
+        let mut map = SrbTreeMap::with_universe_in(100u32, alloc.clone());
+        map.reserve_range(50..);
+        map.reserve_range(..0);
+        let before = allocations();
+        map.extend((50..=100).map(|k| (k, ())));
+        assert_eq!(allocations(), before);
+    }
+
+    #[test]
+    fn test_shared_reads() {
+        let map: SrbTreeMap<u32, u64> = (0..50_000u32)
+            .map(|k| (k.wrapping_mul(2_654_435_761) >> 8, k.into()))
+            .collect();
+        let sum: u64 = map.values().sum();
+        let barrier = std::sync::Barrier::new(8);
+
+        std::thread::scope(|scope| {
+            for thread in 0..8u32 {
+                let (map, barrier) = (&map, &barrier);
+                scope.spawn(move || {
+                    // every thread goes through all kinds of reads, each at a different time
+                    barrier.wait();
+                    for round in 0..4 {
+                        match (thread + round) % 4 {
+                            0 => assert_eq!(map.values().sum::<u64>(), sum),
+                            1 => assert!(map
+                                .keys()
+                                .rev()
+                                .zip(map.keys().rev().skip(1))
+                                .all(|(a, b)| a > b)),
+                            2 => {
+                                for k in (thread..50_000).step_by(7) {
+                                    let key = k.wrapping_mul(2_654_435_761) >> 8;
+                                    assert_eq!(map.get(&key), Some((&key, &k.into())));
+                                }
+                            }
+                            _ => {
+                                let chunks = map.chunks(thread as usize + 1);
+                                let len: usize =
+                                    chunks.iter().map(|chunk| chunk.iter().count()).sum();
+                                assert_eq!(len, map.len());
+                                assert_eq!(chunks[0].first(), map.first());
+                            }
+                        }
+                    }
+                });
+            }
+        });
+    }
+}
@@ -1,96 +1,115 @@
-use std::array;
 use std::fmt::Debug;
+use std::iter::Peekable;
+
+use allocator_api2::alloc::{AllocError, Allocator};
 
 use crate::key::Key;
 
+use super::TreeStats;
+
+pub mod bulk;
+pub mod children;
+pub mod entries;
 pub mod iter;
+pub mod pool;
+pub mod slots;
 
+use children::Children;
+use entries::Entries;
 pub use iter::Keys;
 pub use iter::Pairs;
 pub use iter::Values;
+use pool::Pool;
 
-fn get_non_empty<K, V, const B: usize, const FIRST_LAST: bool>(
-    children: &[Option<Node<K, V, B>>],
-) -> Option<&Node<K, V, B>> {
-    let mut children = children.iter().filter_map(Option::as_ref);
-
-    if FIRST_LAST {
-        children.find(|n| n.len != 0)
-    } else {
-        children.rfind(|n| n.len != 0)
-    }
-}
-
-fn get_non_empty_mut<K, V, const B: usize, const FIRST_LAST: bool>(
-    children: &mut [Option<Node<K, V, B>>],
-) -> Option<&mut Node<K, V, B>> {
-    let mut children = children.iter_mut().filter_map(Option::as_mut);
-
-    if FIRST_LAST {
-        children.find(|n| n.len != 0)
-    } else {
-        children.rfind(|n| n.len != 0)
-    }
-}
-
-pub(super) enum Repr<K, V, const B: usize> {
-    Internal {
-        children: Box<[Option<Node<K, V, B>>; B]>,
-    },
-    Leaf {
-        keys: Box<[Option<K>; B]>,
-        values: Box<[Option<V>; B]>,
-    },
+pub(super) enum Repr<K, V, const B: usize, A: Allocator + Clone> {
+    Internal { children: Children<K, V, B, A> },
+    Leaf { entries: Entries<K, V, B, A> },
 }
 
-impl<K: Debug, V: Debug, const B: usize> Debug for Repr<K, V, B> {
+impl<K: Debug, V: Debug, const B: usize, A: Allocator + Clone> Debug for Repr<K, V, B, A> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Repr::Internal { children } => f
                 .debug_list()
-                .entries(children.iter().flat_map(Option::as_ref))
-                .finish(),
-            Repr::Leaf { keys, values } => f
-                .debug_map()
-                .entries(Iterator::zip(
-                    keys.iter().flat_map(Option::as_ref),
-                    values.iter().flat_map(Option::as_ref),
-                ))
+                .entries(children.iter().map(|(_, child)| child))
                 .finish(),
+            Repr::Leaf { entries } => f.debug_map().entries(entries.iter()).finish(),
         }
     }
 }
 
-pub(super) struct Node<K, V, const B: usize> {
-    pub(super) repr: Repr<K, V, B>,
+pub(super) struct Node<K, V, const B: usize, A: Allocator + Clone> {
+    pub(super) repr: Repr<K, V, B, A>,
     pub(super) len: usize,
 }
 
-impl<K, V, const B: usize> Node<K, V, B> {
-    pub fn new_internal() -> Self {
+impl<K, V, const B: usize, A: Allocator + Clone> Node<K, V, B, A> {
+    pub fn new_internal(alloc: A) -> Self {
         Self {
             repr: Repr::Internal {
-                children: Box::new(array::from_fn(|_| None)),
+                children: Children::new(alloc),
             },
             len: 0,
         }
     }
 
-    pub fn new_leaf() -> Self {
+    pub fn try_new_internal(alloc: A) -> Result<Self, AllocError> {
+        Ok(Self {
+            repr: Repr::Internal {
+                children: Children::try_new(alloc)?,
+            },
+            len: 0,
+        })
+    }
+
+    pub fn new_leaf(alloc: A) -> Self {
         Self {
             repr: Repr::Leaf {
-                keys: Box::new(array::from_fn(|_| None)),
-                values: Box::new(array::from_fn(|_| None)),
+                entries: Entries::new(alloc),
             },
             len: 0,
         }
     }
 
+    /// Returns the allocator this node's children or entries are allocated in.
+    pub fn allocator(&self) -> &A {
+        match &self.repr {
+            Repr::Internal { children } => children.allocator(),
+            Repr::Leaf { entries } => entries.allocator(),
+        }
+    }
+
+    pub fn child(&self, idx: usize) -> Option<&Self> {
+        match &self.repr {
+            Repr::Internal { children } => children.get(idx),
+            Repr::Leaf { .. } => None,
+        }
+    }
+
+    /// Returns the number of children, this is zero for leafs.
+    pub fn child_count(&self) -> usize {
+        match &self.repr {
+            Repr::Internal { children } => children.len(),
+            Repr::Leaf { .. } => 0,
+        }
+    }
+
+    /// Returns whether this node has neither children nor room reserved for entries.
+    pub fn is_bare(&self) -> bool {
+        match &self.repr {
+            Repr::Internal { children } => children.len() == 0,
+            Repr::Leaf { entries } => entries.capacity() == 0,
+        }
+    }
+
     pub fn replace_child_at(&mut self, idx: usize, child: Option<Self>) -> Option<Self> {
         match &mut self.repr {
             Repr::Internal { children } => {
                 let new_len = child.as_ref().map(|child| child.len).unwrap_or_default();
-                let old = std::mem::replace(&mut children[idx], child);
+                let old = match child {
+                    Some(child) => children.insert(idx, child),
+                    None => children.remove(idx),
+                };
                 let old_len = old.as_ref().map(|child| child.len).unwrap_or_default();
 
                 self.len = self.len - old_len + new_len;
@@ -101,81 +120,165 @@ impl<K, V, const B: usize> Node<K, V, B> {
         }
     }
 
-    pub fn replace_key_value_at(
-        &mut self,
-        idx: usize,
-        key_value: Option<(K, V)>,
-    ) -> Option<(K, V)> {
+    /// Removes the child at the given digit without shrinking this node, this never allocates.
+    pub fn take_child(&mut self, idx: usize) -> Option<Self> {
         match &mut self.repr {
-            Repr::Internal { .. } => panic!("can't insert key value into internal node"),
-            Repr::Leaf { keys, values } => {
-                let new_some = key_value.is_some();
-
-                let (key, value) = Option::unzip(key_value);
-                let old = Option::zip(
-                    std::mem::replace(&mut keys[idx], key),
-                    std::mem::replace(&mut values[idx], value),
-                );
+            Repr::Internal { children } => {
+                let old = children.take(idx);
+                self.len -= old.as_ref().map(|child| child.len).unwrap_or_default();
+                old
+            }
+            Repr::Leaf { .. } => None,
+        }
+    }
 
-                let old_some = old.is_some();
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        let old = match &mut self.repr {
+            Repr::Internal { children } => {
+                let (idx, _) = children.iter().find(|(_, child)| child.len != 0)?;
+                let child = children.get_mut(idx)?;
+                let old = child.pop_first();
 
-                match (old_some, new_some) {
-                    (true, false) => self.len -= 1,
-                    (false, true) => self.len += 1,
-                    _ => {}
+                if child.len == 0 {
+                    children.take(idx);
                 }
 
                 old
             }
+            Repr::Leaf { entries } => entries.pop_first(),
+        };
+
+        if old.is_some() {
+            self.len -= 1;
+        }
+
+        old
+    }
+
+    /// Moves all entries out in ascending order, the emptied nodes stay in place.
+    pub fn drain(&mut self, f: &mut impl FnMut((K, V))) {
+        match &mut self.repr {
+            Repr::Internal { children } => {
+                for digit in 0..B {
+                    if let Some(child) = children.get_mut(digit) {
+                        child.drain(f);
+                    }
+                }
+            }
+            Repr::Leaf { entries } => {
+                while let Some(entry) = entries.pop_first() {
+                    f(entry);
+                }
+            }
         }
+
+        self.len = 0;
     }
 
+    /// Returns the number of heap bytes allocated by this node and its descendants.
+    ///
+    /// Nodes are stored inside their parent's children, so this node itself is only counted by its
+    /// parent.
     pub fn storage_bytes(&self) -> usize {
-        std::mem::size_of_val(&self)
-            + match &self.repr {
-                Repr::Internal { children } => children
-                    .iter()
-                    .filter_map(Option::as_ref)
-                    .map(Node::storage_bytes)
-                    .sum::<usize>(),
-                Repr::Leaf { .. } => 0,
+        match &self.repr {
+            Repr::Internal { children } => {
+                children.heap_bytes()
+                    + children
+                        .iter()
+                        .map(|(_, child)| child.storage_bytes())
+                        .sum::<usize>()
             }
+            Repr::Leaf { entries } => entries.heap_bytes(),
+        }
+    }
+
+    /// Adds this node and its descendants to `stats`, `depth` is the distance from the root.
+    pub fn collect_stats(&self, depth: usize, stats: &mut TreeStats) {
+        if stats.nodes_per_depth.len() <= depth {
+            stats.nodes_per_depth.resize(depth + 1, 0);
+        }
+        stats.nodes_per_depth[depth] += 1;
+
+        match &self.repr {
+            Repr::Internal { children } => {
+                if self.len == 0 {
+                    stats.stumps += 1;
+                }
+
+                for (_, child) in children.iter() {
+                    child.collect_stats(depth + 1, stats);
+                }
+            }
+            Repr::Leaf { .. } => {
+                if stats.leaf_fill.len() <= self.len {
+                    stats.leaf_fill.resize(self.len + 1, 0);
+                }
+                stats.leaf_fill[self.len] += 1;
+            }
+        }
     }
 
     pub fn storage_util(&self) -> (usize, usize) {
         match &self.repr {
             Repr::Internal { children } => children
                 .iter()
-                .filter_map(Option::as_ref)
-                .map(Node::storage_util)
+                .map(|(_, child)| child.storage_util())
                 .reduce(|acc, it| (acc.0 + it.0, acc.1 + it.1))
                 .unwrap_or_default(),
-            Repr::Leaf { keys, .. } => (keys.len(), self.len),
+            Repr::Leaf { entries } => (entries.capacity(), self.len),
         }
     }
 }
 
-impl<K, V, const B: usize> Node<K, V, B> {
-    pub fn keys(&self) -> Keys<'_, K, V, B> {
+impl<K, V, const B: usize, A: Allocator + Clone> Node<K, V, B, A> {
+    pub fn keys(&self) -> Keys<'_, K, V, B, A> {
         Keys {
             inner: iter::Iter::new(self),
         }
     }
 
-    pub fn values(&self) -> Values<'_, K, V, B> {
+    pub fn values(&self) -> Values<'_, K, V, B, A> {
         Values {
             inner: iter::Iter::new(self),
         }
     }
 
-    pub fn pairs(&self) -> Pairs<'_, K, V, B> {
+    pub fn pairs(&self) -> Pairs<'_, K, V, B, A> {
         Pairs {
             inner: iter::Iter::new(self),
         }
     }
+
+    pub fn first(&self) -> Option<(&K, &V)> {
+        match &self.repr {
+            Repr::Internal { children } => children.first_non_empty().and_then(Node::first),
+            Repr::Leaf { entries } => entries.first(),
+        }
+    }
+
+    pub fn first_mut(&mut self) -> Option<(&mut K, &mut V)> {
+        match &mut self.repr {
+            Repr::Internal { children } => children.first_non_empty_mut().and_then(Node::first_mut),
+            Repr::Leaf { entries } => entries.first_mut(),
+        }
+    }
+
+    pub fn last(&self) -> Option<(&K, &V)> {
+        match &self.repr {
+            Repr::Internal { children } => children.last_non_empty().and_then(Node::last),
+            Repr::Leaf { entries } => entries.last(),
+        }
+    }
+
+    pub fn last_mut(&mut self) -> Option<(&mut K, &mut V)> {
+        match &mut self.repr {
+            Repr::Internal { children } => children.last_non_empty_mut().and_then(Node::last_mut),
+            Repr::Leaf { entries } => entries.last_mut(),
+        }
+    }
 }
 
-impl<K: Key, V, const B: usize> Node<K, V, B> {
+impl<K: Key, V, const B: usize, A: Allocator + Clone> Node<K, V, B, A> {
     fn assert_depth(&self, depth: usize) {
         match &self.repr {
             Repr::Internal { .. } => {
@@ -191,146 +294,287 @@ impl<K: Key, V, const B: usize> Node<K, V, B> {
         }
     }
 
-    pub fn insert(&mut self, depth: usize, key: K, value: V) -> Option<(K, V)> {
+    /// Creates an empty node fit for the given depth, failing if it can't be allocated.
+    pub fn try_new_at(depth: usize, alloc: A) -> Result<Self, AllocError> {
+        if depth == K::max_depth(B) {
+            Ok(Self::new_leaf(alloc))
+        } else {
+            Self::try_new_internal(alloc)
+        }
+    }
+
+    /// Creates or grows all nodes on the path to the given key so that inserting it afterwards
+    /// doesn't allocate.
+    ///
+    /// Nodes which were already on the path keep their children if this fails and the ones this
+    /// created are removed again, without allocating.
+    pub fn try_reserve_path(&mut self, depth: usize, key: &K) -> Result<(), AllocError> {
         self.assert_depth(depth);
 
+        let idx = key.index_at(B, depth);
         match &mut self.repr {
-            Repr::Internal { children } => match &mut children[key.index_at(B, depth)] {
-                Some(child) => {
-                    self.len += 1;
-                    child.insert(depth + 1, key, value)
+            Repr::Internal { children } => {
+                let created = children.get(idx).is_none();
+                if created {
+                    let child = Self::try_new_at(depth + 1, children.allocator().clone())?;
+                    children.try_reserve(idx)?;
+                    children.insert(idx, child);
                 }
-                child @ None => {
-                    let mut new = if depth + 1 == K::max_depth(B) {
-                        Self::new_leaf()
-                    } else {
-                        Self::new_internal()
-                    };
 
-                    new.insert(depth + 1, key, value);
-                    *child = Some(new);
-                    self.len += 1;
-                    None
+                let result = children
+                    .get_mut(idx)
+                    .expect("child was inserted above")
+                    .try_reserve_path(depth + 1, key);
+                if result.is_err() && created {
+                    children.take(idx);
                 }
-            },
-            Repr::Leaf { .. } => {
-                self.replace_key_value_at(key.index_at(B, depth), Some((key, value)))
+
+                result
             }
+            Repr::Leaf { entries } => entries.try_reserve(key),
         }
     }
 
-    pub fn remove(&mut self, depth: usize, key: &K) -> Option<(K, V)> {
+    /// Creates all nodes below this one which hold the keys in `lo..=hi`, so that inserting them
+    /// afterwards doesn't allocate.
+    ///
+    /// The keys are given by their [`Key::to_usize`] value and must all be below this node.
+    pub fn reserve_range(
+        &mut self,
+        depth: usize,
+        lo: usize,
+        hi: usize,
+        pool: &mut Pool<K, V, B, A>,
+    ) {
         self.assert_depth(depth);
 
-        let idx = key.index_at(B, depth);
         match &mut self.repr {
-            Repr::Internal { children } => match &mut children[idx] {
-                Some(child) => {
-                    let old_len = child.len;
-                    let old = child.remove(depth + 1, key);
-                    let new_len = child.len;
-
-                    if new_len == 0 {
-                        // TODO: i think this may actually increase the complexity by insane an measure
-                        children[idx] = None;
-                    } else if old_len != new_len {
-                        self.len -= 1;
+            Repr::Internal { children } => {
+                // the number of keys below each child, all of them are below the first child if
+                // that doesn't fit into a usize
+                let span = B.checked_pow((K::max_depth(B) - depth) as u32);
+                let (first, last) = match span {
+                    Some(span) => ((lo / span) % B, (hi / span) % B),
+                    None => (0, 0),
+                };
+
+                let missing = (first..=last)
+                    .filter(|digit| children.get(*digit).is_none())
+                    .count();
+                children.reserve(missing);
+
+                for digit in first..=last {
+                    if children.get(digit).is_none() {
+                        let child = pool.new_at(depth + 1, children.allocator().clone());
+                        children.insert(digit, child);
                     }
 
-                    old
+                    let (lo, hi) = match span {
+                        Some(span) => {
+                            let block = lo - lo % span + (digit - first) * span;
+                            (lo.max(block), hi.min(block + (span - 1)))
+                        }
+                        None => (lo, hi),
+                    };
+                    children
+                        .get_mut(digit)
+                        .expect("child was inserted above")
+                        .reserve_range(depth + 1, lo, hi, pool);
                 }
-                None => None,
-            },
-            Repr::Leaf { .. } => self.replace_key_value_at(idx, None),
+            }
+            Repr::Leaf { entries } => entries.reserve_dense(),
         }
     }
 
-    pub fn get(&self, depth: usize, key: &K) -> Option<(&K, &V)> {
+    /// Inserts a key value pair, new nodes are taken from `pool` if it has any.
+    pub fn insert(
+        &mut self,
+        depth: usize,
+        key: K,
+        value: V,
+        pool: &mut Pool<K, V, B, A>,
+    ) -> Option<(K, V)> {
         self.assert_depth(depth);
 
         let idx = key.index_at(B, depth);
-        match &self.repr {
-            Repr::Internal { children } => children
-                .get(idx)
-                .and_then(Option::as_ref)
-                .and_then(|child| child.get(depth + 1, key)),
-            Repr::Leaf { keys, values } => Option::zip(
-                keys.get(idx).and_then(Option::as_ref),
-                values.get(idx).and_then(Option::as_ref),
-            ),
+        let old = match &mut self.repr {
+            Repr::Internal { children } => match children.get_mut(idx) {
+                Some(child) => child.insert(depth + 1, key, value, pool),
+                None => {
+                    let mut new = pool.new_at(depth + 1, children.allocator().clone());
+                    new.insert(depth + 1, key, value, pool);
+                    children.insert(idx, new);
+                    None
+                }
+            },
+            Repr::Leaf { entries } => entries.insert(key, value),
+        };
+
+        if old.is_none() {
+            self.len += 1;
         }
+
+        old
     }
 
-    pub fn get_mut(&mut self, depth: usize, key: &K) -> Option<(&mut K, &mut V)> {
+    /// Inserts all pairs, the pairs below each child are inserted by a rayon task of their own.
+    ///
+    /// Pairs with equal keys replace each other in the order they are given.
+    #[cfg(feature = "rayon")]
+    pub fn par_insert(&mut self, depth: usize, entries: Vec<(K, V)>, pool: &mut Pool<K, V, B, A>)
+    where
+        K: Send,
+        V: Send,
+        A: Send,
+    {
+        use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
         self.assert_depth(depth);
 
-        let idx = key.index_at(B, depth);
-        match &mut self.repr {
-            Repr::Internal { children } => children
-                .get_mut(idx)
-                .and_then(Option::as_mut)
-                .and_then(|child| child.get_mut(depth + 1, key)),
-            Repr::Leaf { keys, values } => Option::zip(
-                keys.get_mut(idx).and_then(Option::as_mut),
-                values.get_mut(idx).and_then(Option::as_mut),
-            ),
+        let Repr::Internal { children } = &mut self.repr else {
+            for (key, value) in entries {
+                self.insert(depth, key, value, pool);
+            }
+            return;
+        };
+
+        let mut parts: Vec<Vec<(K, V)>> = (0..B).map(|_| Vec::new()).collect();
+        for (key, value) in entries {
+            parts[key.index_at(B, depth)].push((key, value));
         }
-    }
 
-    pub fn first(&self) -> Option<(&K, &V)> {
-        match &self.repr {
-            Repr::Internal { children } => {
-                get_non_empty::<K, V, B, true>(&**children).and_then(Node::first)
+        for (digit, part) in parts.iter().enumerate() {
+            if !part.is_empty() && children.get(digit).is_none() {
+                children.insert(digit, pool.new_at(depth + 1, children.allocator().clone()));
             }
-            // TODO: double iteration
-            Repr::Leaf { keys, values } => Option::zip(
-                keys.iter().filter_map(Option::as_ref).next(),
-                values.iter().filter_map(Option::as_ref).next(),
-            ),
         }
+
+        let tasks: Vec<_> = children
+            .children_mut()
+            .into_iter()
+            .filter_map(|(digit, child)| {
+                let part = std::mem::take(&mut parts[digit]);
+                (!part.is_empty()).then_some((child, part))
+            })
+            .collect();
+
+        // the pool isn't shared between tasks, they allocate their nodes themselves
+        tasks.into_par_iter().for_each(|(child, part)| {
+            let mut pool = Pool::new();
+            for (key, value) in part {
+                child.insert(depth + 1, key, value, &mut pool);
+            }
+        });
+
+        self.len = children.iter().map(|(_, child)| child.len).sum();
     }
 
-    pub fn first_mut(&mut self) -> Option<(&mut K, &mut V)> {
+    /// Inserts pairs from `batch` for as long as their keys are accepted and fall below this node,
+    /// the first of them must fall below it.
+    ///
+    /// A pair whose key leaves a child returns to the deepest node holding it instead of starting
+    /// over at the root, so runs of nearby keys each only descend the last few levels.
+    pub fn insert_run<I, F>(
+        &mut self,
+        depth: usize,
+        batch: &mut Peekable<I>,
+        accept: &F,
+        pool: &mut Pool<K, V, B, A>,
+    ) where
+        I: Iterator<Item = (K, V)>,
+        F: Fn(&K) -> bool,
+    {
+        self.assert_depth(depth);
+
+        // keys below this node only differ in their last digits, the root of a trie as tall as the
+        // key type may hold any key
+        let span = B.checked_pow((K::max_depth(B) - depth + 1) as u32);
+        let Some((first, _)) = batch.peek() else {
+            return;
+        };
+        let prefix = span.map(|span| first.to_usize() / span);
+        let below = |key: &K| accept(key) && span.map(|span| key.to_usize() / span) == prefix;
+
         match &mut self.repr {
             Repr::Internal { children } => {
-                get_non_empty_mut::<K, V, B, true>(&mut **children).and_then(Node::first_mut)
+                while let Some(digit) = batch
+                    .peek()
+                    .filter(|(key, _)| below(key))
+                    .map(|(key, _)| key.index_at(B, depth))
+                {
+                    if children.get(digit).is_none() {
+                        let child = pool.new_at(depth + 1, children.allocator().clone());
+                        children.insert(digit, child);
+                    }
+
+                    let child = children.get_mut(digit).expect("child was inserted above");
+                    let len = child.len;
+                    child.insert_run(depth + 1, batch, accept, pool);
+                    self.len += child.len - len;
+                }
+            }
+            Repr::Leaf { entries } => {
+                while let Some((key, value)) = batch.next_if(|(key, _)| below(key)) {
+                    if entries.insert(key, value).is_none() {
+                        self.len += 1;
+                    }
+                }
             }
-            // TODO: see above
-            Repr::Leaf { keys, values } => Option::zip(
-                keys.iter_mut().filter_map(Option::as_mut).next(),
-                values.iter_mut().filter_map(Option::as_mut).next(),
-            ),
         }
     }
 
-    pub fn last(&self) -> Option<(&K, &V)> {
-        match &self.repr {
+    pub fn remove(&mut self, depth: usize, key: &K) -> Option<(K, V)> {
+        self.assert_depth(depth);
+
+        let idx = key.index_at(B, depth);
+        let old = match &mut self.repr {
             Repr::Internal { children } => {
-                get_non_empty::<K, V, B, false>(&**children).and_then(Node::last)
+                let child = children.get_mut(idx)?;
+                let old = child.remove(depth + 1, key);
+
+                if old.is_some() && child.len == 0 {
+                    // TODO: i think this may actually increase the complexity by insane an measure
+                    children.remove(idx);
+                }
+
+                old
             }
-            // TODO: see above
-            Repr::Leaf { keys, values } => Option::zip(
-                keys.iter().rev().filter_map(Option::as_ref).next(),
-                values.iter().rev().filter_map(Option::as_ref).next(),
-            ),
+            Repr::Leaf { entries } => entries.remove(key),
+        };
+
+        if old.is_some() {
+            self.len -= 1;
         }
+
+        old
     }
 
-    pub fn last_mut(&mut self) -> Option<(&mut K, &mut V)> {
+    pub fn get(&self, depth: usize, key: &K) -> Option<(&K, &V)> {
+        self.assert_depth(depth);
+
+        let idx = key.index_at(B, depth);
+        match &self.repr {
+            Repr::Internal { children } => children
+                .get(idx)
+                .and_then(|child| child.get(depth + 1, key)),
+            Repr::Leaf { entries } => entries.get(key),
+        }
+    }
+
+    pub fn get_mut(&mut self, depth: usize, key: &K) -> Option<(&mut K, &mut V)> {
+        self.assert_depth(depth);
+
+        let idx = key.index_at(B, depth);
         match &mut self.repr {
-            Repr::Internal { children } => {
-                get_non_empty_mut::<K, V, B, false>(&mut **children).and_then(Node::last_mut)
-            }
-            // TODO: see above
-            Repr::Leaf { keys, values } => Option::zip(
-                keys.iter_mut().rev().flat_map(Option::as_mut).next(),
-                values.iter_mut().rev().flat_map(Option::as_mut).next(),
-            ),
+            Repr::Internal { children } => children
+                .get_mut(idx)
+                .and_then(|child| child.get_mut(depth + 1, key)),
+            Repr::Leaf { entries } => entries.get_mut(key),
         }
     }
 }
 
-impl<K: Debug, V: Debug, const B: usize> Debug for Node<K, V, B> {
+impl<K: Debug, V: Debug, const B: usize, A: Allocator + Clone> Debug for Node<K, V, B, A> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{} ", self.len)?;
         self.repr.fmt(f)
@@ -0,0 +1,114 @@
+use allocator_api2::alloc::Allocator;
+
+use crate::key::Key;
+
+use super::children::Children;
+use super::entries::Entries;
+use super::{Node, Repr};
+
+/// The finished children of an open internal node by digit.
+type Level<K, V, const B: usize, A> = Vec<(usize, Node<K, V, B, A>)>;
+
+/// Builds a trie from entries in ascending key order, creating every node exactly once.
+///
+/// Only the rightmost path of the trie is open at any time, a node is created once the first key
+/// past it arrives and all of its entries are known.
+pub(in crate::tree) struct Builder<K, V, const B: usize, A: Allocator + Clone> {
+    alloc: A,
+    /// The entries of the open leaf.
+    leaf: Vec<(K, V)>,
+    /// The finished children of the open internal nodes, starting right above the leafs.
+    levels: Vec<Level<K, V, B, A>>,
+    /// The digits of the last key from the leaf upwards, reused between pushes.
+    path: Vec<usize>,
+}
+
+impl<K: Key, V, const B: usize, A: Allocator + Clone> Builder<K, V, B, A> {
+    pub fn new(alloc: A) -> Self {
+        Self {
+            alloc,
+            leaf: Vec::new(),
+            levels: Vec::new(),
+            path: Vec::new(),
+        }
+    }
+
+    /// Returns the last key pushed.
+    pub fn last(&self) -> Option<&K> {
+        self.leaf.last().map(|(k, _)| k)
+    }
+
+    /// Appends an entry, its key must be larger than every key pushed before.
+    pub fn push(&mut self, key: K, value: V) {
+        if let Some(last) = self.last() {
+            debug_assert!(*last < key, "keys must be pushed in ascending order");
+
+            // every open node below the shallowest digit the keys differ in is complete
+            let depth = (0..=K::max_depth(B))
+                .find(|depth| last.index_at(B, *depth) != key.index_at(B, *depth))
+                .expect("pushed keys are distinct");
+            self.close(K::max_depth(B) - depth);
+        }
+
+        self.leaf.push((key, value));
+    }
+
+    /// Returns the root and the height of the finished trie, or `None` if nothing was pushed.
+    pub fn finish(mut self) -> Option<(Node<K, V, B, A>, usize)> {
+        let height = self.last()?.height(B);
+        self.close(height);
+
+        let root = match height {
+            0 => self.take_leaf(),
+            _ => self.take_internal(height),
+        };
+
+        Some((root, height))
+    }
+
+    /// Creates the open nodes below the given level and hands them to their parents.
+    fn close(&mut self, level: usize) {
+        let Some((last, _)) = self.leaf.last().filter(|_| level > 0) else {
+            return;
+        };
+
+        self.path.clear();
+        self.path
+            .extend((1..=level).map(|level| last.index_at(B, K::max_depth(B) - level)));
+
+        if self.levels.len() < level {
+            self.levels.resize_with(level, Vec::new);
+        }
+
+        let mut node = self.take_leaf();
+        for level in 1..=level {
+            self.levels[level - 1].push((self.path[level - 1], node));
+            if level == self.path.len() {
+                break;
+            }
+            node = self.take_internal(level);
+        }
+    }
+
+    fn take_leaf(&mut self) -> Node<K, V, B, A> {
+        let len = self.leaf.len();
+        Node {
+            repr: Repr::Leaf {
+                entries: Entries::from_sorted(self.leaf.drain(..), self.alloc.clone()),
+            },
+            len,
+        }
+    }
+
+    /// Creates the open internal node at the given level from its finished children.
+    fn take_internal(&mut self, level: usize) -> Node<K, V, B, A> {
+        let children = &mut self.levels[level - 1];
+        let len = children.iter().map(|(_, child)| child.len).sum();
+        Node {
+            repr: Repr::Internal {
+                children: Children::from_children(children.drain(..), self.alloc.clone()),
+            },
+            len,
+        }
+    }
+}
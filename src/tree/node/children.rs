@@ -0,0 +1,512 @@
+use std::array;
+use std::iter::{Enumerate, Zip};
+use std::slice;
+
+use allocator_api2::alloc::{AllocError, Allocator};
+use allocator_api2::boxed::Box;
+
+use super::Node;
+
+const NODE4: usize = 4;
+
+/// A slot for a child which may be empty.
+type Child<K, V, const B: usize, A> = Option<Node<K, V, B, A>>;
+
+/// The physical layout of an internal node.
+///
+/// Both kinds address their children by the same digit in `0..B`, they only differ in how many
+/// children they can hold. The tree's fanout of 16 leaves no room for the `Node16` and `Node48`
+/// kinds of a 256-way tree, a sorted node of sixteen children is as large as a full one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Node4,
+    /// A slot for every digit, this is `Node256` in a tree with a fanout of 256.
+    Full,
+}
+
+impl Kind {
+    pub fn capacity(self, branching_factor: usize) -> usize {
+        match self {
+            Kind::Node4 => NODE4,
+            Kind::Full => branching_factor,
+        }
+    }
+
+    /// The smallest kind which holds the given number of children.
+    fn fitting(children: usize, branching_factor: usize) -> Self {
+        if children <= NODE4 && NODE4 < branching_factor {
+            Kind::Node4
+        } else {
+            Kind::Full
+        }
+    }
+
+    fn grown(self, branching_factor: usize) -> Option<Self> {
+        (self == Kind::Node4 && NODE4 < branching_factor).then_some(Kind::Full)
+    }
+
+    fn shrunk(self, branching_factor: usize) -> Option<Self> {
+        (self == Kind::Full && NODE4 < branching_factor).then_some(Kind::Node4)
+    }
+
+    /// The number of children at or below which a node shrinks into this kind.
+    ///
+    /// This is a quarter below the capacity so that a node sitting on a boundary doesn't
+    /// alternate between growing and shrinking.
+    fn shrink_threshold(self, branching_factor: usize) -> usize {
+        let capacity = self.capacity(branching_factor);
+        capacity - capacity / 4
+    }
+}
+
+pub(in crate::tree) struct Sorted<K, V, const B: usize, const N: usize, A: Allocator + Clone> {
+    len: u8,
+    digits: [u8; N],
+    children: [Option<Node<K, V, B, A>>; N],
+}
+
+impl<K, V, const B: usize, const N: usize, A: Allocator + Clone> Sorted<K, V, B, N, A> {
+    fn new() -> Self {
+        Self {
+            len: 0,
+            digits: [0; N],
+            children: array::from_fn(|_| None),
+        }
+    }
+
+    fn position(&self, digit: u8) -> Result<usize, usize> {
+        self.digits[..self.len as usize].binary_search(&digit)
+    }
+
+    fn insert(&mut self, digit: u8, child: Node<K, V, B, A>) -> Option<Node<K, V, B, A>> {
+        let len = self.len as usize;
+        match self.position(digit) {
+            Ok(pos) => self.children[pos].replace(child),
+            Err(pos) => {
+                debug_assert!(len < N, "sorted node is full");
+
+                self.digits.copy_within(pos..len, pos + 1);
+                self.children[pos..=len].rotate_right(1);
+                self.digits[pos] = digit;
+                self.children[pos] = Some(child);
+                self.len += 1;
+                None
+            }
+        }
+    }
+
+    fn remove(&mut self, digit: u8) -> Option<Node<K, V, B, A>> {
+        let len = self.len as usize;
+        let pos = self.position(digit).ok()?;
+
+        let old = self.children[pos].take();
+        self.children[pos..len].rotate_left(1);
+        self.digits.copy_within(pos + 1..len, pos);
+        self.len -= 1;
+
+        old
+    }
+}
+
+pub(in crate::tree) struct Full<K, V, const B: usize, A: Allocator + Clone> {
+    len: u16,
+    children: [Child<K, V, B, A>; B],
+}
+
+impl<K, V, const B: usize, A: Allocator + Clone> Full<K, V, B, A> {
+    fn new() -> Self {
+        Self {
+            len: 0,
+            children: array::from_fn(|_| None),
+        }
+    }
+
+    fn insert(&mut self, digit: usize, child: Node<K, V, B, A>) -> Option<Node<K, V, B, A>> {
+        let old = self.children[digit].replace(child);
+        self.len += u16::from(old.is_none());
+        old
+    }
+
+    fn remove(&mut self, digit: usize) -> Option<Node<K, V, B, A>> {
+        let old = self.children[digit].take();
+        self.len -= u16::from(old.is_some());
+        old
+    }
+}
+
+pub(in crate::tree) enum Children<K, V, const B: usize, A: Allocator + Clone> {
+    Node4(Box<Sorted<K, V, B, NODE4, A>, A>),
+    Full(Box<Full<K, V, B, A>, A>),
+}
+
+impl<K, V, const B: usize, A: Allocator + Clone> Children<K, V, B, A> {
+    /// Sorted nodes store their digits as `u8`.
+    const ASSERT_B: () = assert!(B <= 256, "children only support up to 256 digits");
+
+    pub fn new(alloc: A) -> Self {
+        Self::with_kind(Kind::fitting(0, B), alloc)
+    }
+
+    pub fn try_new(alloc: A) -> Result<Self, AllocError> {
+        Self::try_with_kind(Kind::fitting(0, B), alloc)
+    }
+
+    /// Creates a node of the smallest kind holding the given children, which must have distinct
+    /// digits.
+    pub fn from_children<I>(children: I, alloc: A) -> Self
+    where
+        I: ExactSizeIterator<Item = (usize, Node<K, V, B, A>)>,
+    {
+        let mut this = Self::with_kind(Kind::fitting(children.len(), B), alloc);
+        for (digit, child) in children {
+            this.insert(digit, child);
+        }
+
+        this
+    }
+
+    fn with_kind(kind: Kind, alloc: A) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::ASSERT_B;
+
+        match kind {
+            Kind::Node4 => Self::Node4(Box::new_in(Sorted::new(), alloc)),
+            Kind::Full => Self::Full(Box::new_in(Full::new(), alloc)),
+        }
+    }
+
+    fn try_with_kind(kind: Kind, alloc: A) -> Result<Self, AllocError> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::ASSERT_B;
+
+        Ok(match kind {
+            Kind::Node4 => Self::Node4(Box::try_new_in(Sorted::new(), alloc)?),
+            Kind::Full => Self::Full(Box::try_new_in(Full::new(), alloc)?),
+        })
+    }
+
+    pub fn allocator(&self) -> &A {
+        match self {
+            Self::Node4(node) => Box::allocator(node),
+            Self::Full(node) => Box::allocator(node),
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        match self {
+            Self::Node4(_) => Kind::Node4,
+            Self::Full(_) => Kind::Full,
+        }
+    }
+
+    /// Returns the number of children, this is not the number of entries below this node.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Node4(node) => node.len as usize,
+            Self::Full(node) => node.len as usize,
+        }
+    }
+
+    pub fn get(&self, digit: usize) -> Option<&Node<K, V, B, A>> {
+        match self {
+            Self::Node4(node) => node.children[node.position(digit as u8).ok()?].as_ref(),
+            Self::Full(node) => node.children[digit].as_ref(),
+        }
+    }
+
+    pub fn get_mut(&mut self, digit: usize) -> Option<&mut Node<K, V, B, A>> {
+        match self {
+            Self::Node4(node) => {
+                let pos = node.position(digit as u8).ok()?;
+                node.children[pos].as_mut()
+            }
+            Self::Full(node) => node.children[digit].as_mut(),
+        }
+    }
+
+    /// Returns the kind this node has to grow into before a child can be inserted at the given
+    /// digit.
+    fn grows_into(&self, digit: usize) -> Option<Kind> {
+        if self.get(digit).is_none() && self.len() == self.kind().capacity(B) {
+            self.kind().grown(B)
+        } else {
+            None
+        }
+    }
+
+    /// Grows this node if necessary so that inserting a child at the given digit doesn't
+    /// allocate, this node is left as is if the allocation fails.
+    pub fn try_reserve(&mut self, digit: usize) -> Result<(), AllocError> {
+        if let Some(kind) = self.grows_into(digit) {
+            let new = Self::try_with_kind(kind, self.allocator().clone())?;
+            self.convert(new);
+        }
+
+        Ok(())
+    }
+
+    /// Grows this node so that `additional` more children fit without growing again.
+    pub fn reserve(&mut self, additional: usize) {
+        let kind = Kind::fitting(self.len() + additional, B).max(self.kind());
+        if kind != self.kind() {
+            self.convert(Self::with_kind(kind, self.allocator().clone()));
+        }
+    }
+
+    /// Inserts a child at the given digit, growing into a larger kind if necessary.
+    pub fn insert(&mut self, digit: usize, child: Node<K, V, B, A>) -> Option<Node<K, V, B, A>> {
+        if let Some(kind) = self.grows_into(digit) {
+            self.convert(Self::with_kind(kind, self.allocator().clone()));
+        }
+
+        match self {
+            Self::Node4(node) => node.insert(digit as u8, child),
+            Self::Full(node) => node.insert(digit, child),
+        }
+    }
+
+    /// Removes the child at the given digit without shrinking, this never allocates.
+    pub fn take(&mut self, digit: usize) -> Option<Node<K, V, B, A>> {
+        match self {
+            Self::Node4(node) => node.remove(digit as u8),
+            Self::Full(node) => node.remove(digit),
+        }
+    }
+
+    /// Removes the child at the given digit, shrinking into a smaller kind if it falls far
+    /// enough below its capacity.
+    pub fn remove(&mut self, digit: usize) -> Option<Node<K, V, B, A>> {
+        let old = self.take(digit);
+
+        if let Some(kind) = self.kind().shrunk(B) {
+            if self.len() <= kind.shrink_threshold(B) {
+                self.convert(Self::with_kind(kind, self.allocator().clone()));
+            }
+        }
+
+        old
+    }
+
+    /// Moves all children into `new` and replaces this node with it.
+    fn convert(&mut self, mut new: Self) {
+        let mut move_child = |digit: usize, child: &mut Option<Node<K, V, B, A>>| {
+            if let Some(child) = child.take() {
+                new.insert(digit, child);
+            }
+        };
+
+        match self {
+            Self::Node4(node) => {
+                let node = &mut **node;
+                let len = node.len as usize;
+                for (digit, child) in Iterator::zip(node.digits[..len].iter(), &mut node.children) {
+                    move_child(*digit as usize, child);
+                }
+            }
+            Self::Full(node) => {
+                for (digit, child) in node.children.iter_mut().enumerate() {
+                    move_child(digit, child);
+                }
+            }
+        }
+
+        *self = new;
+    }
+
+    /// Returns the number of bytes allocated for the children themselves, this doesn't include
+    /// the allocations of the children.
+    pub fn heap_bytes(&self) -> usize {
+        match self {
+            Self::Node4(node) => std::mem::size_of_val(&**node),
+            Self::Full(node) => std::mem::size_of_val(&**node),
+        }
+    }
+
+    pub fn first_non_empty(&self) -> Option<&Node<K, V, B, A>> {
+        self.iter()
+            .map(|(_, child)| child)
+            .find(|child| child.len != 0)
+    }
+
+    pub fn first_non_empty_mut(&mut self) -> Option<&mut Node<K, V, B, A>> {
+        let (digit, _) = self.iter().find(|(_, child)| child.len != 0)?;
+        self.get_mut(digit)
+    }
+
+    pub fn last_non_empty(&self) -> Option<&Node<K, V, B, A>> {
+        self.iter()
+            .map(|(_, child)| child)
+            .rfind(|child| child.len != 0)
+    }
+
+    pub fn last_non_empty_mut(&mut self) -> Option<&mut Node<K, V, B, A>> {
+        let (digit, _) = self.iter().rfind(|(_, child)| child.len != 0)?;
+        self.get_mut(digit)
+    }
+
+    /// Returns an iterator over the digits and children in ascending order of their digits.
+    pub fn iter(&self) -> Iter<'_, K, V, B, A> {
+        Iter {
+            inner: match self {
+                Self::Node4(node) => IterInner::Sorted(Iterator::zip(
+                    node.digits[..node.len as usize].iter(),
+                    node.children[..node.len as usize].iter(),
+                )),
+                Self::Full(node) => IterInner::Full(node.children.iter().enumerate()),
+            },
+        }
+    }
+
+    /// Returns mutable references to all children together with their digits, in digit order.
+    #[cfg(feature = "rayon")]
+    pub fn children_mut(&mut self) -> Vec<(usize, &mut Node<K, V, B, A>)> {
+        fn sorted<K, V, const B: usize, const N: usize, A: Allocator + Clone>(
+            node: &mut Sorted<K, V, B, N, A>,
+        ) -> Vec<(usize, &mut Node<K, V, B, A>)> {
+            let len = node.len as usize;
+            Iterator::zip(node.digits[..len].iter(), node.children[..len].iter_mut())
+                .filter_map(|(digit, child)| Some((*digit as usize, child.as_mut()?)))
+                .collect()
+        }
+
+        match self {
+            Self::Node4(node) => sorted(node),
+            Self::Full(node) => node
+                .children
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(digit, child)| Some((digit, child.as_mut()?)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum IterInner<'n, K, V, const B: usize, A: Allocator + Clone> {
+    Sorted(Zip<slice::Iter<'n, u8>, slice::Iter<'n, Child<K, V, B, A>>>),
+    Full(Enumerate<slice::Iter<'n, Child<K, V, B, A>>>),
+}
+
+#[derive(Debug)]
+pub(in crate::tree) struct Iter<'n, K, V, const B: usize, A: Allocator + Clone> {
+    inner: IterInner<'n, K, V, B, A>,
+}
+
+impl<'n, K, V, const B: usize, A: Allocator + Clone> Iter<'n, K, V, B, A> {
+    pub fn empty() -> Self {
+        Self {
+            inner: IterInner::Full([].iter().enumerate()),
+        }
+    }
+}
+
+impl<'n, K, V, const B: usize, A: Allocator + Clone> Iterator for Iter<'n, K, V, B, A> {
+    type Item = (usize, &'n Node<K, V, B, A>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.inner {
+            IterInner::Sorted(iter) => {
+                iter.find_map(|(digit, child)| Some((*digit as usize, child.as_ref()?)))
+            }
+            IterInner::Full(iter) => iter.find_map(|(digit, child)| Some((digit, child.as_ref()?))),
+        }
+    }
+}
+
+impl<'n, K, V, const B: usize, A: Allocator + Clone> DoubleEndedIterator for Iter<'n, K, V, B, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match &mut self.inner {
+            IterInner::Sorted(iter) => iter
+                .rev()
+                .find_map(|(digit, child)| Some((*digit as usize, child.as_ref()?))),
+            IterInner::Full(iter) => iter
+                .rev()
+                .find_map(|(digit, child)| Some((digit, child.as_ref()?))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use allocator_api2::alloc::Global;
+
+    use super::*;
+
+    fn leaf() -> Node<u16, (), 16, Global> {
+        Node::new_leaf(Global)
+    }
+
+    fn digits(children: &Children<u16, (), 16, Global>) -> Vec<usize> {
+        children.iter().map(|(digit, _)| digit).collect()
+    }
+
+    #[test]
+    fn test_kinds() {
+        assert_eq!(Kind::fitting(0, 16), Kind::Node4);
+        assert_eq!(Kind::fitting(5, 16), Kind::Full);
+        assert_eq!(Kind::Node4.grown(16), Some(Kind::Full));
+        assert_eq!(Kind::Full.grown(16), None);
+        assert_eq!(Kind::Full.shrunk(16), Some(Kind::Node4));
+
+        // a node of four children is already full with a fanout of four
+        assert_eq!(Kind::fitting(0, 4), Kind::Full);
+        assert_eq!(Kind::Full.shrunk(4), None);
+    }
+
+    #[test]
+    fn test_grow() {
+        let mut children = Children::<u16, (), 16, _>::new(Global);
+
+        for digit in (0..16).rev() {
+            children.insert(digit, leaf());
+
+            let expected = match children.len() {
+                0..=4 => Kind::Node4,
+                _ => Kind::Full,
+            };
+            assert_eq!(children.kind(), expected);
+            assert_eq!(digits(&children), (digit..16).collect::<Vec<_>>());
+        }
+
+        // replacing a child keeps the count, removing one lowers it
+        assert!(children.insert(7, leaf()).is_some());
+        assert_eq!(children.len(), 16);
+        assert!(children.remove(7).is_some());
+        assert!(children.remove(7).is_none());
+        assert_eq!(children.len(), 15);
+    }
+
+    #[test]
+    fn test_shrink_hysteresis() {
+        let mut children = Children::<u16, (), 16, _>::new(Global);
+
+        for digit in 0..5 {
+            children.insert(digit, leaf());
+        }
+        assert_eq!(children.kind(), Kind::Full);
+
+        // back at the boundary, but not below the shrink threshold
+        children.remove(4);
+        assert_eq!(children.kind(), Kind::Full);
+        children.insert(4, leaf());
+        assert_eq!(children.kind(), Kind::Full);
+
+        children.remove(4);
+        children.remove(0);
+        assert_eq!(children.kind(), Kind::Node4);
+        assert_eq!(digits(&children), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_iter_rev() {
+        for step in [3, 5] {
+            let mut children = Children::<u16, (), 16, _>::new(Global);
+            for digit in (0..16).step_by(step) {
+                children.insert(digit, leaf());
+            }
+
+            let rev: Vec<_> = children.iter().rev().map(|(digit, _)| digit).collect();
+            assert_eq!(rev, (0..16).step_by(step).rev().collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use std::slice;
+
+use allocator_api2::alloc::{AllocError, Allocator};
+use allocator_api2::boxed::Box;
+use allocator_api2::vec::Vec;
+
+use crate::key::Key;
+
+use super::slots::{self, Slots};
+
+/// The physical layout of a leaf node.
+///
+/// Leafs start out sparse and switch to the dense layout once they hold more than a quarter of
+/// `B` entries. They only switch back once they fall to an eighth of `B` so that a leaf sitting on
+/// the boundary doesn't alternate between both.
+pub(in crate::tree) enum Entries<K, V, const B: usize, A: Allocator + Clone> {
+    /// Entries sorted by key, within a leaf this is the same as sorting them by digit.
+    Sparse(Vec<(K, V), A>),
+    /// A slot for every digit.
+    Dense(Box<Slots<K, V, B>, A>),
+}
+
+impl<K, V, const B: usize, A: Allocator + Clone> Entries<K, V, B, A> {
+    const SPARSE_MAX: usize = B / 4;
+    const SPARSE_MIN: usize = B / 8;
+
+    pub fn new(alloc: A) -> Self {
+        Self::Sparse(Vec::new_in(alloc))
+    }
+
+    pub fn allocator(&self) -> &A {
+        match self {
+            Self::Sparse(entries) => entries.allocator(),
+            Self::Dense(slots) => Box::allocator(slots),
+        }
+    }
+
+    /// Returns the number of entries this leaf can hold without changing its layout.
+    pub fn capacity(&self) -> usize {
+        match self {
+            Self::Sparse(entries) => entries.capacity(),
+            Self::Dense(_) => B,
+        }
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        match self {
+            Self::Sparse(entries) => entries.capacity() * std::mem::size_of::<(K, V)>(),
+            Self::Dense(slots) => std::mem::size_of_val(&**slots),
+        }
+    }
+
+    /// Removes all entries, keeping the allocation and layout.
+    pub fn clear(&mut self) {
+        match self {
+            Self::Sparse(entries) => entries.clear(),
+            Self::Dense(slots) => slots.clear(),
+        }
+    }
+
+    pub fn first(&self) -> Option<(&K, &V)> {
+        self.iter().next()
+    }
+
+    pub fn last(&self) -> Option<(&K, &V)> {
+        self.iter().next_back()
+    }
+
+    pub fn first_mut(&mut self) -> Option<(&mut K, &mut V)> {
+        match self {
+            Self::Sparse(entries) => entries.first_mut(),
+            Self::Dense(slots) => slots.first_mut(),
+        }
+        .map(|(k, v)| (k, v))
+    }
+
+    pub fn last_mut(&mut self) -> Option<(&mut K, &mut V)> {
+        match self {
+            Self::Sparse(entries) => entries.last_mut(),
+            Self::Dense(slots) => slots.last_mut(),
+        }
+        .map(|(k, v)| (k, v))
+    }
+
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        match self {
+            Self::Sparse(entries) => (!entries.is_empty()).then(|| entries.remove(0)),
+            Self::Dense(slots) => slots.pop_first(),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V, B> {
+        match self {
+            Self::Sparse(entries) => Iter::Sparse(entries.iter()),
+            Self::Dense(slots) => Iter::Dense(slots.iter()),
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        let (sparse, dense) = match self {
+            Self::Sparse(entries) => (Some(entries.iter_mut()), None),
+            Self::Dense(slots) => (None, Some(slots.iter_mut())),
+        };
+
+        sparse
+            .into_iter()
+            .flatten()
+            .chain(dense.into_iter().flatten())
+            .map(|(k, v)| (&*k, v))
+    }
+
+    fn into_dense(
+        entries: &mut Vec<(K, V), A>,
+        mut slots: Box<Slots<K, V, B>, A>,
+        digit: impl Fn(&K) -> usize,
+    ) -> Self {
+        for entry in entries.drain(..) {
+            slots.replace(digit(&entry.0), entry);
+        }
+
+        Self::Dense(slots)
+    }
+
+    fn into_sparse(slots: &mut Box<Slots<K, V, B>, A>) -> Self {
+        let mut entries = Vec::with_capacity_in(slots.len(), Box::allocator(slots).clone());
+        while let Some(entry) = slots.pop_first() {
+            entries.push(entry);
+        }
+
+        Self::Sparse(entries)
+    }
+}
+
+impl<K: Key, V, const B: usize, A: Allocator + Clone> Entries<K, V, B, A> {
+    fn digit(key: &K) -> usize {
+        key.index_at(B, K::max_depth(B))
+    }
+
+    pub fn get(&self, key: &K) -> Option<(&K, &V)> {
+        match self {
+            Self::Sparse(entries) => {
+                let idx = entries.binary_search_by(|(k, _)| k.cmp(key)).ok()?;
+                entries.get(idx)
+            }
+            Self::Dense(slots) => slots.get(Self::digit(key)),
+        }
+        .map(|(k, v)| (k, v))
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<(&mut K, &mut V)> {
+        match self {
+            Self::Sparse(entries) => {
+                let idx = entries.binary_search_by(|(k, _)| k.cmp(key)).ok()?;
+                entries.get_mut(idx)
+            }
+            Self::Dense(slots) => slots.get_mut(Self::digit(key)),
+        }
+        .map(|(k, v)| (k, v))
+    }
+
+    /// Creates a leaf holding the given entries in the layout inserting them would end up in, the
+    /// entries must be sorted by key and all belong into the same leaf.
+    pub fn from_sorted<I: ExactSizeIterator<Item = (K, V)>>(entries: I, alloc: A) -> Self {
+        if entries.len() <= Self::SPARSE_MAX {
+            let mut sparse = Vec::with_capacity_in(entries.len(), alloc);
+            sparse.extend(entries);
+            Self::Sparse(sparse)
+        } else {
+            let mut slots = Slots::new_in(alloc);
+            for entry in entries {
+                slots.replace(Self::digit(&entry.0), entry);
+            }
+            Self::Dense(slots)
+        }
+    }
+
+    /// Makes room for the given key so that inserting it doesn't allocate, this leaf is left as
+    /// is if the allocation fails.
+    pub fn try_reserve(&mut self, key: &K) -> Result<(), AllocError> {
+        let Self::Sparse(entries) = self else {
+            return Ok(());
+        };
+
+        match entries.binary_search_by(|(k, _)| k.cmp(key)) {
+            Ok(_) => {}
+            Err(_) if entries.len() < Self::SPARSE_MAX => {
+                entries.try_reserve_exact(1).map_err(|_| AllocError)?;
+            }
+            Err(_) => {
+                let slots = Slots::try_new_in(entries.allocator().clone())?;
+                *self = Self::into_dense(entries, slots, Self::digit);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switches to the dense layout so that inserting any key doesn't allocate.
+    pub fn reserve_dense(&mut self) {
+        if let Self::Sparse(entries) = self {
+            let slots = Slots::new_in(entries.allocator().clone());
+            *self = Self::into_dense(entries, slots, Self::digit);
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if let Self::Sparse(entries) = self {
+            match entries.binary_search_by(|(k, _)| k.cmp(&key)) {
+                Ok(idx) => return Some(std::mem::replace(&mut entries[idx], (key, value))),
+                Err(idx) if entries.len() < Self::SPARSE_MAX => {
+                    // sparse leafs are small enough to grow them one entry at a time
+                    entries.reserve_exact(1);
+                    entries.insert(idx, (key, value));
+                    return None;
+                }
+                Err(_) => {
+                    let slots = Slots::new_in(entries.allocator().clone());
+                    *self = Self::into_dense(entries, slots, Self::digit);
+                }
+            }
+        }
+
+        match self {
+            Self::Sparse(_) => unreachable!("sparse leafs are handled above"),
+            Self::Dense(slots) => slots.replace(Self::digit(&key), (key, value)),
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<(K, V)> {
+        match self {
+            Self::Sparse(entries) => {
+                let idx = entries.binary_search_by(|(k, _)| k.cmp(key)).ok()?;
+                Some(entries.remove(idx))
+            }
+            Self::Dense(slots) => {
+                let old = slots.take(Self::digit(key));
+                if old.is_some() && slots.len() <= Self::SPARSE_MIN {
+                    *self = Self::into_sparse(slots);
+                }
+
+                old
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(in crate::tree) enum Iter<'n, K, V, const B: usize> {
+    Sparse(slice::Iter<'n, (K, V)>),
+    Dense(slots::Iter<'n, K, V, B>),
+}
+
+impl<'n, K, V, const B: usize> Iter<'n, K, V, B> {
+    pub fn empty() -> Self {
+        Self::Sparse([].iter())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Sparse(entries) => entries.len() == 0,
+            Self::Dense(slots) => slots.is_empty(),
+        }
+    }
+}
+
+impl<'n, K, V, const B: usize> Iterator for Iter<'n, K, V, B> {
+    type Item = (&'n K, &'n V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Sparse(entries) => entries.next(),
+            Self::Dense(slots) => slots.next(),
+        }
+        .map(|(k, v)| (k, v))
+    }
+}
+
+impl<'n, K, V, const B: usize> DoubleEndedIterator for Iter<'n, K, V, B> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Sparse(entries) => entries.next_back(),
+            Self::Dense(slots) => slots.next_back(),
+        }
+        .map(|(k, v)| (k, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use allocator_api2::alloc::Global;
+
+    use super::*;
+
+    #[test]
+    fn test_switch_layout() {
+        let mut entries = Entries::<u32, u32, 16, _>::new(Global);
+
+        for key in [3, 1, 2, 0] {
+            assert_eq!(entries.insert(key, key), None);
+            assert!(matches!(entries, Entries::Sparse(_)));
+        }
+
+        assert_eq!(entries.insert(15, 15), None);
+        assert!(matches!(entries, Entries::Dense(_)));
+        assert_eq!(entries.get(&15), Some((&15, &15)));
+        assert_eq!(entries.get(&4), None);
+
+        // stays dense until it falls well below the sparse capacity
+        for key in [0, 1] {
+            assert!(entries.remove(&key).is_some());
+            assert!(matches!(entries, Entries::Dense(_)));
+        }
+
+        assert_eq!(entries.remove(&15), Some((15, 15)));
+        assert!(matches!(entries, Entries::Sparse(_)));
+        assert_eq!(entries.iter().collect::<Vec<_>>(), [(&2, &2), (&3, &3)]);
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut sparse = Entries::<u32, (), 16, _>::new(Global);
+        let mut dense = Entries::<u32, (), 16, _>::new(Global);
+
+        for key in [9, 4, 1] {
+            sparse.insert(key, ());
+        }
+        for key in [14, 9, 7, 4, 3, 1] {
+            dense.insert(key, ());
+        }
+
+        let keys = |entries: &Entries<u32, (), 16, Global>| -> Vec<u32> {
+            entries.iter().map(|(k, _)| *k).collect()
+        };
+        let rev_keys = |entries: &Entries<u32, (), 16, Global>| -> Vec<u32> {
+            entries.iter().rev().map(|(k, _)| *k).collect()
+        };
+
+        assert_eq!(keys(&sparse), [1, 4, 9]);
+        assert_eq!(rev_keys(&sparse), [9, 4, 1]);
+        assert_eq!(keys(&dense), [1, 3, 4, 7, 9, 14]);
+        assert_eq!(rev_keys(&dense), [14, 9, 7, 4, 3, 1]);
+    }
+}
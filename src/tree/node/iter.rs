@@ -1,153 +1,59 @@
-use super::{Node, Repr};
+use allocator_api2::alloc::Allocator;
 
-#[derive(Debug)]
-struct InternalIter<'n, K, V, const B: usize> {
-    children: &'n [Option<Node<K, V, B>>],
-}
-
-impl<'n, K, V, const B: usize> InternalIter<'n, K, V, B> {
-    fn yield_left(&mut self) -> Option<&'n Node<K, V, B>> {
-        loop {
-            if let Some(child) = self.next()? {
-                if child.len != 0 {
-                    return Some(child);
-                }
-            }
-        }
-    }
-
-    fn yield_right(&mut self) -> Option<&'n Node<K, V, B>> {
-        loop {
-            if let Some(child) = self.next_back()? {
-                if child.len != 0 {
-                    return Some(child);
-                }
-            }
-        }
-    }
-}
-
-impl<'n, K: 'n, V: 'n, const B: usize> Iterator for InternalIter<'n, K, V, B> {
-    type Item = &'n Option<Node<K, V, B>>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let (child, rest) = self.children.split_first()?;
-        self.children = rest;
-
-        Some(child)
-    }
-
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.children.len(), Some(self.children.len()))
-    }
-}
-
-impl<'n, K: 'n, V: 'n, const B: usize> DoubleEndedIterator for InternalIter<'n, K, V, B> {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        let (child, rest) = self.children.split_last()?;
-        self.children = rest;
-
-        Some(child)
-    }
-}
-
-impl<'n, K: 'n, V: 'n, const B: usize> ExactSizeIterator for InternalIter<'n, K, V, B> {}
+use super::{children, entries, Node, Repr};
 
 #[derive(Debug)]
-struct LeafIter<'n, K, V, const B: usize> {
-    keys: &'n [Option<K>],
-    values: &'n [Option<V>],
+struct InternalIter<'n, K, V, const B: usize, A: Allocator + Clone> {
+    children: children::Iter<'n, K, V, B, A>,
 }
 
-impl<'n, K, V, const B: usize> LeafIter<'n, K, V, B> {
-    fn yield_left(&mut self) -> Option<(&'n K, &'n V)> {
-        loop {
-            if let (Some(k), Some(v)) = self.next()? {
-                return Some((k, v));
-            }
-        }
-    }
-
-    fn yield_right(&mut self) -> Option<(&'n K, &'n V)> {
-        loop {
-            if let (Some(k), Some(v)) = self.next_back()? {
-                return Some((k, v));
-            }
+impl<'n, K, V, const B: usize, A: Allocator + Clone> InternalIter<'n, K, V, B, A> {
+    fn empty() -> Self {
+        Self {
+            children: children::Iter::empty(),
         }
     }
-}
-
-impl<'n, K, V, const B: usize> Iterator for LeafIter<'n, K, V, B> {
-    type Item = (&'n Option<K>, &'n Option<V>);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let (k, krest) = self.keys.split_first()?;
-        let (v, vrest) = self.values.split_first()?;
-
-        self.keys = krest;
-        self.values = vrest;
-
-        Some((k, v))
-    }
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.keys.len(), Some(self.keys.len()))
+    fn yield_left(&mut self) -> Option<&'n Node<K, V, B, A>> {
+        self.children
+            .find(|(_, child)| child.len != 0)
+            .map(|(_, child)| child)
     }
-}
-
-impl<'n, K, V, const B: usize> DoubleEndedIterator for LeafIter<'n, K, V, B> {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        let (k, krest) = self.keys.split_last()?;
-        let (v, vrest) = self.values.split_last()?;
-
-        self.keys = krest;
-        self.values = vrest;
 
-        Some((k, v))
+    fn yield_right(&mut self) -> Option<&'n Node<K, V, B, A>> {
+        self.children
+            .rfind(|(_, child)| child.len != 0)
+            .map(|(_, child)| child)
     }
 }
 
-impl<'n, K, V, const B: usize> ExactSizeIterator for LeafIter<'n, K, V, B> {}
-
 #[derive(Debug)]
-pub(super) struct Iter<'n, K, V, const B: usize> {
-    common: InternalIter<'n, K, V, B>,
-    left: Vec<InternalIter<'n, K, V, B>>,
-    right: Vec<InternalIter<'n, K, V, B>>,
-    left_leaf: LeafIter<'n, K, V, B>,
-    right_leaf: LeafIter<'n, K, V, B>,
+pub(super) struct Iter<'n, K, V, const B: usize, A: Allocator + Clone> {
+    common: InternalIter<'n, K, V, B, A>,
+    left: Vec<InternalIter<'n, K, V, B, A>>,
+    right: Vec<InternalIter<'n, K, V, B, A>>,
+    left_leaf: entries::Iter<'n, K, V, B>,
+    right_leaf: entries::Iter<'n, K, V, B>,
 }
 
-impl<'n, K, V, const B: usize> Iter<'n, K, V, B> {
-    pub fn new(node: &'n Node<K, V, B>) -> Self {
+impl<'n, K, V, const B: usize, A: Allocator + Clone> Iter<'n, K, V, B, A> {
+    pub fn new(node: &'n Node<K, V, B, A>) -> Self {
         match &node.repr {
             Repr::Internal { children } => Self {
                 common: InternalIter {
-                    children: &**children,
+                    children: children.iter(),
                 },
                 left: vec![],
                 right: vec![],
-                left_leaf: LeafIter {
-                    keys: &[],
-                    values: &[],
-                },
-                right_leaf: LeafIter {
-                    keys: &[],
-                    values: &[],
-                },
+                left_leaf: entries::Iter::empty(),
+                right_leaf: entries::Iter::empty(),
             },
-            Repr::Leaf { keys, values } => Self {
-                common: InternalIter { children: &[] },
+            Repr::Leaf { entries } => Self {
+                common: InternalIter::empty(),
                 left: vec![],
                 right: vec![],
-                left_leaf: LeafIter {
-                    keys: &**keys,
-                    values: &**values,
-                },
-                right_leaf: LeafIter {
-                    keys: &[],
-                    values: &[],
-                },
+                left_leaf: entries.iter(),
+                right_leaf: entries::Iter::empty(),
             },
         }
     }
@@ -178,7 +84,7 @@ impl<'n, K, V, const B: usize> Iter<'n, K, V, B> {
         }
 
         // use other child in case it still has any
-        if !self.right_leaf.keys.is_empty() {
+        if !self.right_leaf.is_empty() {
             std::mem::swap(&mut self.left_leaf, &mut self.right_leaf);
             return Some(());
         }
@@ -212,7 +118,7 @@ impl<'n, K, V, const B: usize> Iter<'n, K, V, B> {
         }
 
         // use other child in case it still has any
-        if !self.left_leaf.keys.is_empty() {
+        if !self.left_leaf.is_empty() {
             std::mem::swap(&mut self.left_leaf, &mut self.right_leaf);
             return Some(());
         }
@@ -220,61 +126,55 @@ impl<'n, K, V, const B: usize> Iter<'n, K, V, B> {
         None
     }
 
-    fn descend_left(&mut self, child: &'n Node<K, V, B>) {
+    fn descend_left(&mut self, child: &'n Node<K, V, B, A>) {
         match &child.repr {
             Repr::Internal { children } => {
                 let mut current = InternalIter {
-                    children: &**children,
+                    children: children.iter(),
                 };
 
                 let child = current.yield_left();
                 self.left.push(current);
-                match child {
-                    Some(child) => self.descend_left(child),
-                    // these stumps appear after removals without cleanup
-                    None => {}
+
+                // stumps without any children appear after removals without cleanup
+                if let Some(child) = child {
+                    self.descend_left(child);
                 }
             }
-            Repr::Leaf { keys, values } => {
-                self.left_leaf = LeafIter {
-                    keys: &**keys,
-                    values: &**values,
-                };
+            Repr::Leaf { entries } => {
+                self.left_leaf = entries.iter();
             }
         }
     }
 
-    fn descend_right(&mut self, child: &'n Node<K, V, B>) {
+    fn descend_right(&mut self, child: &'n Node<K, V, B, A>) {
         match &child.repr {
             Repr::Internal { children } => {
                 let mut current = InternalIter {
-                    children: &**children,
+                    children: children.iter(),
                 };
 
                 let child = current.yield_right();
                 self.right.push(current);
-                match child {
-                    Some(child) => self.descend_right(child),
-                    // these stumps appear after removals without cleanup
-                    None => {}
+
+                // stumps without any children appear after removals without cleanup
+                if let Some(child) = child {
+                    self.descend_right(child);
                 }
             }
-            Repr::Leaf { keys, values } => {
-                self.right_leaf = LeafIter {
-                    keys: &**keys,
-                    values: &**values,
-                };
+            Repr::Leaf { entries } => {
+                self.right_leaf = entries.iter();
             }
         }
     }
 }
 
-impl<'n, K, V, const B: usize> Iterator for Iter<'n, K, V, B> {
+impl<'n, K, V, const B: usize, A: Allocator + Clone> Iterator for Iter<'n, K, V, B, A> {
     type Item = (&'n K, &'n V);
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            if let Some(kv) = self.left_leaf.yield_left() {
+            if let Some(kv) = self.left_leaf.next() {
                 return Some(kv);
             }
 
@@ -283,10 +183,10 @@ impl<'n, K, V, const B: usize> Iterator for Iter<'n, K, V, B> {
     }
 }
 
-impl<'n, K, V, const B: usize> DoubleEndedIterator for Iter<'n, K, V, B> {
+impl<'n, K, V, const B: usize, A: Allocator + Clone> DoubleEndedIterator for Iter<'n, K, V, B, A> {
     fn next_back(&mut self) -> Option<Self::Item> {
         loop {
-            if let Some(kv) = self.right_leaf.yield_right() {
+            if let Some(kv) = self.right_leaf.next_back() {
                 return Some(kv);
             }
 
@@ -296,11 +196,11 @@ impl<'n, K, V, const B: usize> DoubleEndedIterator for Iter<'n, K, V, B> {
 }
 
 #[derive(Debug)]
-pub struct Keys<'n, K, V, const B: usize> {
-    pub(super) inner: Iter<'n, K, V, B>,
+pub struct Keys<'n, K, V, const B: usize, A: Allocator + Clone> {
+    pub(super) inner: Iter<'n, K, V, B, A>,
 }
 
-impl<'n, K, V, const B: usize> Iterator for Keys<'n, K, V, B> {
+impl<'n, K, V, const B: usize, A: Allocator + Clone> Iterator for Keys<'n, K, V, B, A> {
     type Item = &'n K;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -308,18 +208,18 @@ impl<'n, K, V, const B: usize> Iterator for Keys<'n, K, V, B> {
     }
 }
 
-impl<'n, K, V, const B: usize> DoubleEndedIterator for Keys<'n, K, V, B> {
+impl<'n, K, V, const B: usize, A: Allocator + Clone> DoubleEndedIterator for Keys<'n, K, V, B, A> {
     fn next_back(&mut self) -> Option<Self::Item> {
         self.inner.next_back().map(|(k, _)| k)
     }
 }
 
 #[derive(Debug)]
-pub struct Values<'n, K, V, const B: usize> {
-    pub(super) inner: Iter<'n, K, V, B>,
+pub struct Values<'n, K, V, const B: usize, A: Allocator + Clone> {
+    pub(super) inner: Iter<'n, K, V, B, A>,
 }
 
-impl<'n, K, V, const B: usize> Iterator for Values<'n, K, V, B> {
+impl<'n, K, V, const B: usize, A: Allocator + Clone> Iterator for Values<'n, K, V, B, A> {
     type Item = &'n V;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -327,18 +227,20 @@ impl<'n, K, V, const B: usize> Iterator for Values<'n, K, V, B> {
     }
 }
 
-impl<'n, K, V, const B: usize> DoubleEndedIterator for Values<'n, K, V, B> {
+impl<'n, K, V, const B: usize, A: Allocator + Clone> DoubleEndedIterator
+    for Values<'n, K, V, B, A>
+{
     fn next_back(&mut self) -> Option<Self::Item> {
         self.inner.next_back().map(|(_, v)| v)
     }
 }
 
 #[derive(Debug)]
-pub struct Pairs<'n, K, V, const B: usize> {
-    pub(super) inner: Iter<'n, K, V, B>,
+pub struct Pairs<'n, K, V, const B: usize, A: Allocator + Clone> {
+    pub(super) inner: Iter<'n, K, V, B, A>,
 }
 
-impl<'n, K, V, const B: usize> Iterator for Pairs<'n, K, V, B> {
+impl<'n, K, V, const B: usize, A: Allocator + Clone> Iterator for Pairs<'n, K, V, B, A> {
     type Item = (&'n K, &'n V);
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -346,7 +248,7 @@ impl<'n, K, V, const B: usize> Iterator for Pairs<'n, K, V, B> {
     }
 }
 
-impl<'n, K, V, const B: usize> DoubleEndedIterator for Pairs<'n, K, V, B> {
+impl<'n, K, V, const B: usize, A: Allocator + Clone> DoubleEndedIterator for Pairs<'n, K, V, B, A> {
     fn next_back(&mut self) -> Option<Self::Item> {
         self.inner.next_back()
     }
@@ -354,13 +256,16 @@ impl<'n, K, V, const B: usize> DoubleEndedIterator for Pairs<'n, K, V, B> {
 
 #[cfg(test)]
 mod tests {
+    use allocator_api2::alloc::Global;
+
+    use super::super::pool::Pool;
     use super::*;
 
-    fn iter() -> Keys<'static, u16, (), 16> {
-        let node = Box::leak(Box::new(Node::new_internal()));
+    fn iter() -> Keys<'static, u16, (), 16, Global> {
+        let node = Box::leak(Box::new(Node::new_internal(Global)));
 
         for val in (0..100).step_by(10) {
-            node.insert(0, val, ());
+            node.insert(0, val, (), &mut Pool::new());
         }
 
         node.keys()
@@ -0,0 +1,112 @@
+use allocator_api2::alloc::Allocator;
+
+use crate::key::Key;
+
+use super::children::Children;
+use super::entries::Entries;
+use super::{Node, Repr};
+
+/// Emptied node arrays which are reused for new nodes instead of allocating them again.
+///
+/// The pool is filled when a tree is cleared and holds at most `limit` arrays, it is disabled
+/// with a limit of zero.
+pub(in crate::tree) struct Pool<K, V, const B: usize, A: Allocator + Clone> {
+    limit: usize,
+    internals: Vec<Children<K, V, B, A>>,
+    leafs: Vec<Entries<K, V, B, A>>,
+}
+
+impl<K, V, const B: usize, A: Allocator + Clone> Pool<K, V, B, A> {
+    pub const fn new() -> Self {
+        Self {
+            limit: 0,
+            internals: Vec::new(),
+            leafs: Vec::new(),
+        }
+    }
+
+    /// Returns the number of pooled node arrays.
+    pub fn len(&self) -> usize {
+        self.internals.len() + self.leafs.len()
+    }
+
+    /// Sets the number of node arrays this pool retains, releasing any above it.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        while self.len() > limit {
+            if self.leafs.pop().is_none() {
+                self.internals.pop();
+            }
+        }
+    }
+
+    /// Releases all pooled node arrays, the limit is kept.
+    pub fn shrink(&mut self) {
+        self.internals = Vec::new();
+        self.leafs = Vec::new();
+    }
+
+    /// Returns the number of heap bytes held by pooled node arrays.
+    pub fn heap_bytes(&self) -> usize {
+        self.internals
+            .iter()
+            .map(Children::heap_bytes)
+            .sum::<usize>()
+            + self.leafs.iter().map(Entries::heap_bytes).sum::<usize>()
+    }
+
+    /// Empties the given node and its descendants into the pool, dropping whatever doesn't fit.
+    pub fn recycle(&mut self, node: Node<K, V, B, A>) {
+        if self.len() >= self.limit {
+            return;
+        }
+
+        match node.repr {
+            Repr::Internal { mut children } => {
+                while let Some((digit, _)) = children.iter().next() {
+                    let child = children.take(digit).expect("digit was just yielded");
+                    self.recycle(child);
+                }
+
+                if self.len() < self.limit {
+                    self.internals.push(children);
+                }
+            }
+            Repr::Leaf { mut entries } => {
+                entries.clear();
+                self.leafs.push(entries);
+            }
+        }
+    }
+
+    pub fn new_internal(&mut self, alloc: A) -> Node<K, V, B, A> {
+        match self.internals.pop() {
+            Some(children) => Node {
+                repr: Repr::Internal { children },
+                len: 0,
+            },
+            None => Node::new_internal(alloc),
+        }
+    }
+
+    pub fn new_leaf(&mut self, alloc: A) -> Node<K, V, B, A> {
+        match self.leafs.pop() {
+            Some(entries) => Node {
+                repr: Repr::Leaf { entries },
+                len: 0,
+            },
+            None => Node::new_leaf(alloc),
+        }
+    }
+}
+
+impl<K: Key, V, const B: usize, A: Allocator + Clone> Pool<K, V, B, A> {
+    /// Creates an empty node fit for the given depth.
+    pub fn new_at(&mut self, depth: usize, alloc: A) -> Node<K, V, B, A> {
+        if depth == K::max_depth(B) {
+            self.new_leaf(alloc)
+        } else {
+            self.new_internal(alloc)
+        }
+    }
+}
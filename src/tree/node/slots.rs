@@ -0,0 +1,352 @@
+use std::mem::MaybeUninit;
+
+use allocator_api2::alloc::{AllocError, Allocator};
+use allocator_api2::boxed::Box;
+
+/// A bitmask of occupied slots, this supports up to 256 slots just like digits of internal nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Mask([u64; 4]);
+
+impl Mask {
+    const EMPTY: Self = Self([0; 4]);
+
+    fn contains(&self, idx: usize) -> bool {
+        self.0[idx / 64] & (1 << (idx % 64)) != 0
+    }
+
+    fn insert(&mut self, idx: usize) {
+        self.0[idx / 64] |= 1 << (idx % 64);
+    }
+
+    fn remove(&mut self, idx: usize) {
+        self.0[idx / 64] &= !(1 << (idx % 64));
+    }
+
+    fn count(&self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    fn first(&self) -> Option<usize> {
+        self.0
+            .iter()
+            .enumerate()
+            .find(|(_, word)| **word != 0)
+            .map(|(idx, word)| idx * 64 + word.trailing_zeros() as usize)
+    }
+
+    fn last(&self) -> Option<usize> {
+        self.0
+            .iter()
+            .enumerate()
+            .rfind(|(_, word)| **word != 0)
+            .map(|(idx, word)| idx * 64 + 63 - word.leading_zeros() as usize)
+    }
+}
+
+/// A slot for every digit of a leaf, stored in a single allocation together with the mask of
+/// occupied slots.
+pub(in crate::tree) struct Slots<K, V, const B: usize> {
+    mask: Mask,
+    slots: [MaybeUninit<(K, V)>; B],
+}
+
+impl<K, V, const B: usize> Slots<K, V, B> {
+    const ASSERT_B: () = assert!(B <= 256, "slots only support up to 256 digits");
+
+    pub fn new_in<A: Allocator>(alloc: A) -> Box<Self, A> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::ASSERT_B;
+
+        Box::new_in(
+            Self {
+                mask: Mask::EMPTY,
+                slots: [const { MaybeUninit::uninit() }; B],
+            },
+            alloc,
+        )
+    }
+
+    pub fn try_new_in<A: Allocator>(alloc: A) -> Result<Box<Self, A>, AllocError> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::ASSERT_B;
+
+        Box::try_new_in(
+            Self {
+                mask: Mask::EMPTY,
+                slots: [const { MaybeUninit::uninit() }; B],
+            },
+            alloc,
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.mask.count()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&(K, V)> {
+        // SAFETY: the mask only contains initialized slots
+        self.mask
+            .contains(idx)
+            .then(|| unsafe { self.slots[idx].assume_init_ref() })
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut (K, V)> {
+        // SAFETY: the mask only contains initialized slots
+        self.mask
+            .contains(idx)
+            .then(|| unsafe { self.slots[idx].assume_init_mut() })
+    }
+
+    pub fn first_mut(&mut self) -> Option<&mut (K, V)> {
+        self.get_mut(self.mask.first()?)
+    }
+
+    pub fn last_mut(&mut self) -> Option<&mut (K, V)> {
+        self.get_mut(self.mask.last()?)
+    }
+
+    /// Puts an entry into the given slot, returning the previous one.
+    pub fn replace(&mut self, idx: usize, entry: (K, V)) -> Option<(K, V)> {
+        match self.get_mut(idx) {
+            Some(old) => Some(std::mem::replace(old, entry)),
+            None => {
+                self.slots[idx].write(entry);
+                self.mask.insert(idx);
+                None
+            }
+        }
+    }
+
+    /// Takes the entry out of the given slot, leaving it empty.
+    pub fn take(&mut self, idx: usize) -> Option<(K, V)> {
+        if !self.mask.contains(idx) {
+            return None;
+        }
+
+        self.mask.remove(idx);
+
+        // SAFETY: the slot was in the mask, it's removed from it so it's not read again
+        Some(unsafe { self.slots[idx].assume_init_read() })
+    }
+
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        self.take(self.mask.first()?)
+    }
+
+    pub fn clear(&mut self) {
+        self.drop_entries();
+    }
+
+    fn drop_entries(&mut self) {
+        while let Some(idx) = self.mask.first() {
+            self.mask.remove(idx);
+
+            // SAFETY: the slot was in the mask, it's removed from it before it's dropped
+            unsafe { self.slots[idx].assume_init_drop() };
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V, B> {
+        Iter {
+            mask: self.mask,
+            slots: &self.slots,
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut (K, V)> {
+        let mask = self.mask;
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter(move |(idx, _)| mask.contains(*idx))
+            // SAFETY: the mask only contains initialized slots
+            .map(|(_, slot)| unsafe { slot.assume_init_mut() })
+    }
+}
+
+impl<K, V, const B: usize> Drop for Slots<K, V, B> {
+    fn drop(&mut self) {
+        /// Keeps dropping the remaining entries if dropping one of them panics.
+        struct Guard<'a, K, V, const B: usize>(&'a mut Slots<K, V, B>);
+
+        impl<K, V, const B: usize> Drop for Guard<'_, K, V, B> {
+            fn drop(&mut self) {
+                self.0.drop_entries();
+            }
+        }
+
+        let guard = Guard(self);
+        guard.0.drop_entries();
+    }
+}
+
+#[derive(Debug)]
+pub(in crate::tree) struct Iter<'n, K, V, const B: usize> {
+    /// The slots which have not been yielded yet.
+    mask: Mask,
+    slots: &'n [MaybeUninit<(K, V)>; B],
+}
+
+impl<'n, K, V, const B: usize> Iter<'n, K, V, B> {
+    pub fn is_empty(&self) -> bool {
+        self.mask == Mask::EMPTY
+    }
+}
+
+impl<'n, K, V, const B: usize> Iterator for Iter<'n, K, V, B> {
+    type Item = &'n (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let idx = self.mask.first()?;
+        self.mask.remove(idx);
+
+        // SAFETY: the mask only contains initialized slots
+        Some(unsafe { self.slots[idx].assume_init_ref() })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.mask.count(), Some(self.mask.count()))
+    }
+}
+
+impl<'n, K, V, const B: usize> DoubleEndedIterator for Iter<'n, K, V, B> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let idx = self.mask.last()?;
+        self.mask.remove(idx);
+
+        // SAFETY: the mask only contains initialized slots
+        Some(unsafe { self.slots[idx].assume_init_ref() })
+    }
+}
+
+impl<'n, K, V, const B: usize> ExactSizeIterator for Iter<'n, K, V, B> {}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    use allocator_api2::alloc::Global;
+
+    use super::*;
+
+    /// Counts how often it was dropped, optionally panicking when it is.
+    #[derive(Debug)]
+    struct Tracked {
+        drops: Rc<Cell<usize>>,
+        panics: bool,
+    }
+
+    impl Tracked {
+        fn new(drops: &Rc<Cell<usize>>) -> Self {
+            Self {
+                drops: Rc::clone(drops),
+                panics: false,
+            }
+        }
+    }
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+            if self.panics {
+                panic!("tracked value panicked on drop");
+            }
+        }
+    }
+
+    #[test]
+    fn test_mask() {
+        let mut mask = Mask::EMPTY;
+        assert_eq!(mask.first(), None);
+        assert_eq!(mask.last(), None);
+
+        for idx in [3, 64, 200] {
+            mask.insert(idx);
+        }
+
+        assert!(mask.contains(64));
+        assert!(!mask.contains(65));
+        assert_eq!(mask.count(), 3);
+        assert_eq!(mask.first(), Some(3));
+        assert_eq!(mask.last(), Some(200));
+
+        mask.remove(3);
+        assert_eq!(mask.first(), Some(64));
+    }
+
+    #[test]
+    fn test_insert_replace_remove() {
+        let drops = Rc::new(Cell::new(0));
+        let mut slots = Slots::<u8, Tracked, 16>::new_in(Global);
+
+        assert!(slots.replace(3, (3, Tracked::new(&drops))).is_none());
+        assert!(slots.replace(7, (7, Tracked::new(&drops))).is_none());
+        assert_eq!(slots.len(), 2);
+        assert_eq!(drops.get(), 0);
+
+        let old = slots.replace(3, (3, Tracked::new(&drops)));
+        assert!(old.is_some());
+        drop(old);
+        assert_eq!(drops.get(), 1);
+
+        let old = slots.take(7);
+        assert_eq!(old.as_ref().map(|(k, _)| *k), Some(7));
+        assert!(slots.take(7).is_none());
+        drop(old);
+        assert_eq!(drops.get(), 2);
+
+        assert_eq!(slots.len(), 1);
+        assert_eq!(slots.iter().map(|(k, _)| *k).collect::<Vec<_>>(), [3]);
+    }
+
+    #[test]
+    fn test_drop() {
+        let drops = Rc::new(Cell::new(0));
+        let mut slots = Slots::<u8, Tracked, 16>::new_in(Global);
+
+        for idx in [0, 5, 15] {
+            slots.replace(idx, (idx as u8, Tracked::new(&drops)));
+        }
+        slots.take(5);
+        assert_eq!(drops.get(), 1);
+
+        drop(slots);
+        assert_eq!(drops.get(), 3);
+    }
+
+    #[test]
+    fn test_drop_panic() {
+        let drops = Rc::new(Cell::new(0));
+        let mut slots = Slots::<u8, Tracked, 16>::new_in(Global);
+
+        for idx in 0..4 {
+            let mut tracked = Tracked::new(&drops);
+            tracked.panics = idx == 1;
+            slots.replace(idx, (idx as u8, tracked));
+        }
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| drop(slots)));
+        assert!(result.is_err());
+        assert_eq!(drops.get(), 4);
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut slots = Slots::<u8, (), 16>::new_in(Global);
+        for idx in [1, 4, 9, 14] {
+            slots.replace(idx, (idx as u8, ()));
+        }
+
+        let mut iter = slots.iter();
+        assert_eq!(iter.len(), 4);
+        assert_eq!(iter.next(), Some(&(1, ())));
+        assert_eq!(iter.next_back(), Some(&(14, ())));
+        assert_eq!(iter.next_back(), Some(&(9, ())));
+        assert_eq!(iter.next(), Some(&(4, ())));
+        assert!(iter.is_empty());
+        assert_eq!(iter.next(), None);
+    }
+}
@@ -0,0 +1,697 @@
+use std::array;
+use std::iter;
+use std::marker::PhantomData;
+use std::ptr;
+
+use crossbeam_epoch::{self as epoch, Guard};
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+#[cfg(not(loom))]
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+use crate::key::Key;
+
+use super::B;
+
+/// Waits a moment before a descent restarts.
+fn backoff() {
+    #[cfg(loom)]
+    loom::thread::yield_now();
+    #[cfg(not(loom))]
+    std::hint::spin_loop();
+}
+
+/// A version counter whose lowest bits mark a node as locked by a writer or unlinked from the trie.
+struct VersionLock(AtomicU64);
+
+impl VersionLock {
+    const OBSOLETE: u64 = 0b01;
+    const LOCKED: u64 = 0b10;
+
+    fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    /// Returns the current version, or `None` if a writer holds the node or it was unlinked.
+    fn read(&self) -> Option<u64> {
+        let version = self.0.load(Ordering::Acquire);
+        (version & (Self::OBSOLETE | Self::LOCKED) == 0).then_some(version)
+    }
+
+    /// Returns whether nothing was written to the node since `version` was read.
+    fn validate(&self, version: u64) -> bool {
+        self.0.load(Ordering::Acquire) == version
+    }
+
+    /// Locks the node if nothing was written to it since `version` was read.
+    fn upgrade(&self, version: u64) -> bool {
+        self.0
+            .compare_exchange(
+                version,
+                version + Self::LOCKED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    /// Unlocks the node and moves it to the next version.
+    fn unlock(&self) {
+        self.0.fetch_add(Self::LOCKED, Ordering::Release);
+    }
+
+    /// Unlocks a node which was unlinked from the trie, readers still reaching it restart.
+    fn unlock_obsolete(&self) {
+        self.0
+            .fetch_add(Self::LOCKED + Self::OBSOLETE, Ordering::Release);
+    }
+}
+
+enum Slots<K, V> {
+    Internal([AtomicPtr<OlcNode<K, V>>; B]),
+    Leaf([AtomicPtr<(K, V)>; B]),
+}
+
+struct OlcNode<K, V> {
+    version: VersionLock,
+    /// The number of occupied slots, this is only written while the node is locked.
+    len: AtomicUsize,
+    /// Readers can tell the height of the root they loaded from this, it never changes.
+    depth: usize,
+    slots: Slots<K, V>,
+}
+
+impl<K: Key, V> OlcNode<K, V> {
+    fn new_at(depth: usize) -> *mut Self {
+        let slots = if depth == K::max_depth(B) {
+            Slots::Leaf(array::from_fn(|_| AtomicPtr::new(ptr::null_mut())))
+        } else {
+            Slots::Internal(array::from_fn(|_| AtomicPtr::new(ptr::null_mut())))
+        };
+
+        Box::into_raw(Box::new(Self {
+            version: VersionLock::new(),
+            len: AtomicUsize::new(0),
+            depth,
+            slots,
+        }))
+    }
+
+    fn children(&self) -> &[AtomicPtr<Self>; B] {
+        match &self.slots {
+            Slots::Internal(children) => children,
+            Slots::Leaf(_) => unreachable!("leafs have no children"),
+        }
+    }
+
+    fn entry(&self, key: &K) -> &AtomicPtr<(K, V)> {
+        match &self.slots {
+            Slots::Leaf(entries) => &entries[key.index_at(B, K::max_depth(B))],
+            Slots::Internal(_) => unreachable!("internal nodes have no entries"),
+        }
+    }
+}
+
+/// Frees an allocation once every thread which was pinned when it was unlinked is unpinned.
+///
+/// # Safety
+///
+/// `ptr` must come from `Box::into_raw` and must not be reachable from the map anymore.
+unsafe fn retire<T>(ptr: *mut T, guard: &Guard) {
+    // SAFETY: threads pinned later can't reach the allocation, the caller makes sure it may be
+    // dropped on another thread
+    unsafe { guard.defer_unchecked(move || drop(Box::from_raw(ptr))) };
+}
+
+/// Another thread wrote to a node while it was read, the descent starts over from the root.
+struct Restart;
+
+/// A node passed by a descent, the version read from it and the digit taken below it.
+type Step<'g, K, V> = (&'g OlcNode<K, V>, u64, usize);
+
+/// A leaf reached by an optimistic descent, together with the version read from it.
+struct Position<'g, K, V> {
+    leaf: &'g OlcNode<K, V>,
+    version: u64,
+}
+
+/// A map for several concurrent writers, using optimistic lock coupling.
+///
+/// Every node carries a version lock. Readers never write to shared memory, they remember the
+/// version of each node they pass and restart from the root if it changed by the time they are
+/// done with the node. Writers descend the same way and only lock the node they modify, plus the
+/// parent of a leaf they empty and unlink.
+///
+/// Replaced entries and unlinked nodes are handed to the epoch collector like in
+/// [`super::concurrent::ConcurrentSrbTreeMap`], they are freed once no pinned reader can hold
+/// them anymore. Emptying a leaf unlinks it together with every ancestor it leaves empty, only
+/// the root stays.
+pub struct OlcSrbTreeMap<K, V> {
+    root: AtomicPtr<OlcNode<K, V>>,
+    len: AtomicUsize,
+    /// Entries are read from every thread, so the map is only `Sync` if they are. Atomic pointers
+    /// alone would make it `Sync` regardless.
+    marker: PhantomData<(K, V)>,
+}
+
+impl<K, V> OlcSrbTreeMap<K, V> {
+    pub fn new() -> Self {
+        Self {
+            root: AtomicPtr::new(ptr::null_mut()),
+            len: AtomicUsize::new(0),
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of entries, this may be stale by the time it is used if other threads
+    /// are writing.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Pins the current thread, entries borrowed through the guard stay valid until it is dropped.
+    pub fn pin(&self) -> Guard {
+        epoch::pin()
+    }
+}
+
+impl<K, V> Default for OlcSrbTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Key, V> OlcSrbTreeMap<K, V> {
+    /// Returns the root if it is high enough to hold the key.
+    fn root_for<'g>(&self, key: &K, _guard: &'g Guard) -> Option<&'g OlcNode<K, V>> {
+        // SAFETY: the root is never unlinked, it is only freed when the map is dropped
+        let root = unsafe { self.root.load(Ordering::Acquire).as_ref() }?;
+        (key.height(B) <= K::max_depth(B) - root.depth).then_some(root)
+    }
+
+    /// Finds the leaf holding the given key, creating the nodes on its path if `create` is set.
+    ///
+    /// The internal nodes passed on the way are pushed to `path` if one is given.
+    fn descend<'g>(
+        &self,
+        mut node: &'g OlcNode<K, V>,
+        key: &K,
+        create: bool,
+        mut path: Option<&mut Vec<Step<'g, K, V>>>,
+        _guard: &'g Guard,
+    ) -> Result<Option<Position<'g, K, V>>, Restart> {
+        let mut version = node.version.read().ok_or(Restart)?;
+
+        loop {
+            let Slots::Internal(children) = &node.slots else {
+                return Ok(Some(Position {
+                    leaf: node,
+                    version,
+                }));
+            };
+
+            let digit = key.index_at(B, node.depth);
+            let child = children[digit].load(Ordering::Acquire);
+            // SAFETY: an unlinked node is only freed once every thread pinned before it was
+            // unlinked is unpinned, and the guard pins this one
+            match unsafe { child.as_ref() } {
+                Some(child) => {
+                    // lock coupling, the child is only valid if its parent didn't change meanwhile
+                    let child_version = child.version.read();
+                    if !node.version.validate(version) {
+                        return Err(Restart);
+                    }
+
+                    if let Some(path) = path.as_deref_mut() {
+                        path.push((node, version, digit));
+                    }
+                    node = child;
+                    version = child_version.ok_or(Restart)?;
+                }
+                None if create => {
+                    if !node.version.upgrade(version) {
+                        return Err(Restart);
+                    }
+
+                    children[digit].store(OlcNode::new_at(node.depth + 1), Ordering::Release);
+                    node.len.fetch_add(1, Ordering::Relaxed);
+                    node.version.unlock();
+                    version = node.version.read().ok_or(Restart)?;
+                }
+                None if node.version.validate(version) => return Ok(None),
+                None => return Err(Restart),
+            }
+        }
+    }
+
+    pub fn get<'g>(&'g self, key: &K, guard: &'g Guard) -> Option<(&'g K, &'g V)> {
+        loop {
+            // the root is loaded again on every attempt since it may have grown meanwhile
+            let root = self.root_for(key, guard)?;
+            match self.descend(root, key, false, None, guard) {
+                Ok(None) => return None,
+                Ok(Some(Position { leaf, version })) => {
+                    let entry = leaf.entry(key).load(Ordering::Acquire);
+                    if leaf.version.validate(version) {
+                        // SAFETY: replaced entries are only freed once every guard pinned before
+                        // is dropped
+                        return unsafe { entry.as_ref() }.map(|(k, v)| (k, v));
+                    }
+                }
+                Err(Restart) => {}
+            }
+
+            backoff();
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key, &epoch::pin()).is_some()
+    }
+}
+
+/// Writes hand replaced entries and unlinked nodes to the epoch collector, which may drop them on
+/// any thread and after the borrows they hold may have ended.
+impl<K: Key + Send + 'static, V: Send + 'static> OlcSrbTreeMap<K, V> {
+    /// Adds levels above the root until it is at least at the given height and returns it.
+    fn grow<'g>(&self, height: usize, _guard: &'g Guard) -> &'g OlcNode<K, V> {
+        let depth = K::max_depth(B) - height;
+        loop {
+            let root = self.root.load(Ordering::Acquire);
+            // SAFETY: the root is never unlinked, it is only freed when the map is dropped
+            let new = match unsafe { root.as_ref() } {
+                None => OlcNode::new_at(depth),
+                Some(node) if node.depth <= depth => return node,
+                Some(node) => {
+                    let new = OlcNode::new_at(node.depth - 1);
+                    // SAFETY: the node was just created and isn't shared yet
+                    let new_ref = unsafe { &*new };
+                    new_ref.children()[0].store(root, Ordering::Relaxed);
+                    new_ref.len.store(1, Ordering::Relaxed);
+                    new
+                }
+            };
+
+            let swapped =
+                self.root
+                    .compare_exchange(root, new, Ordering::AcqRel, Ordering::Acquire);
+            if swapped.is_err() {
+                // SAFETY: the node was never shared, dropping it doesn't drop the old root
+                drop(unsafe { Box::from_raw(new) });
+            }
+        }
+    }
+
+    /// Inserts a key value pair, returning whether the key is new.
+    ///
+    /// A previous entry for the key is dropped once no reader can see it anymore.
+    pub fn insert(&self, key: K, value: V) -> bool {
+        let guard = &epoch::pin();
+        let entry = Box::into_raw(Box::new((key, value)));
+        // SAFETY: the entry is only ever read after this, and once it is published it is only
+        // freed after the guard is dropped
+        let key = unsafe { &(*entry).0 };
+
+        loop {
+            let root = self.grow(key.height(B), guard);
+            if let Ok(Some(Position { leaf, version })) = self.descend(root, key, true, None, guard)
+            {
+                if leaf.version.upgrade(version) {
+                    let old = leaf.entry(key).swap(entry, Ordering::AcqRel);
+                    if old.is_null() {
+                        leaf.len.fetch_add(1, Ordering::Relaxed);
+                        self.len.fetch_add(1, Ordering::Relaxed);
+                    }
+                    leaf.version.unlock();
+
+                    if old.is_null() {
+                        return true;
+                    }
+                    // SAFETY: the swap replaced the only pointer to the old entry while the leaf
+                    // was locked, so only threads pinned before can still reach it
+                    unsafe { retire(old, guard) };
+                    return false;
+                }
+            }
+
+            backoff();
+        }
+    }
+
+    /// Removes a key, returning whether it was present.
+    ///
+    /// Removing the last entry of a leaf unlinks the leaf and every ancestor left empty from the
+    /// lowest ancestor which keeps other children, this locks all of them.
+    pub fn remove(&self, key: &K) -> bool {
+        let guard = &epoch::pin();
+        let mut path = Vec::new();
+
+        loop {
+            let Some(root) = self.root_for(key, guard) else {
+                return false;
+            };
+            path.clear();
+            let Position { leaf, version } =
+                match self.descend(root, key, false, Some(&mut path), guard) {
+                    Ok(None) => return false,
+                    Ok(Some(position)) => position,
+                    Err(Restart) => {
+                        backoff();
+                        continue;
+                    }
+                };
+
+            let slot = leaf.entry(key);
+            if slot.load(Ordering::Acquire).is_null() {
+                if leaf.version.validate(version) {
+                    return false;
+                }
+                backoff();
+                continue;
+            }
+
+            // the ancestor the emptied part of the path hangs off, it is the root at the latest
+            let anchor = match leaf.len.load(Ordering::Relaxed) {
+                1 if !path.is_empty() => path
+                    .iter()
+                    .rposition(|(node, _, _)| node.len.load(Ordering::Relaxed) != 1)
+                    .unwrap_or(0),
+                _ => path.len(),
+            };
+
+            // locks are taken top down, so two writers can't wait on each other
+            let locks: Vec<_> = path[anchor..]
+                .iter()
+                .map(|(node, version, _)| (*node, *version))
+                .chain(iter::once((leaf, version)))
+                .collect();
+            let locked = locks
+                .iter()
+                .take_while(|(node, version)| node.version.upgrade(*version))
+                .count();
+            if locked < locks.len() {
+                for (node, _) in &locks[..locked] {
+                    node.version.unlock();
+                }
+                backoff();
+                continue;
+            }
+
+            let old = slot.swap(ptr::null_mut(), Ordering::AcqRel);
+            leaf.len.fetch_sub(1, Ordering::Relaxed);
+            self.len.fetch_sub(1, Ordering::Relaxed);
+
+            match path.get(anchor) {
+                Some(&(anchor, _, digit)) => {
+                    anchor.children()[digit].store(ptr::null_mut(), Ordering::Release);
+                    anchor.len.fetch_sub(1, Ordering::Relaxed);
+                    for (node, _) in &locks[1..] {
+                        node.version.unlock_obsolete();
+                        // SAFETY: the node hung below the anchor, which no longer links it, and
+                        // writers reaching it through an old pointer see it is obsolete
+                        unsafe { retire(ptr::from_ref(*node).cast_mut(), guard) };
+                    }
+                    anchor.version.unlock();
+                }
+                None => leaf.version.unlock(),
+            }
+
+            // SAFETY: the swap took the only pointer to the entry while its leaf was locked
+            unsafe { retire(old, guard) };
+            return true;
+        }
+    }
+}
+
+impl<K, V> Drop for OlcSrbTreeMap<K, V> {
+    fn drop(&mut self) {
+        /// Frees a linked node and everything below it.
+        fn free<K, V>(node: *mut OlcNode<K, V>) {
+            if node.is_null() {
+                return;
+            }
+
+            // SAFETY: the map is borrowed mutably, so no other thread can reach its nodes
+            let node = unsafe { Box::from_raw(node) };
+            match &node.slots {
+                Slots::Internal(children) => {
+                    for child in children {
+                        free(child.load(Ordering::Relaxed));
+                    }
+                }
+                Slots::Leaf(entries) => {
+                    for entry in entries {
+                        let entry = entry.load(Ordering::Relaxed);
+                        if !entry.is_null() {
+                            // SAFETY: see above
+                            drop(unsafe { Box::from_raw(entry) });
+                        }
+                    }
+                }
+            }
+        }
+
+        free(self.root.load(Ordering::Relaxed));
+    }
+}
+
+impl<K: Key + Send + 'static, V: Send + 'static> Extend<(K, V)> for OlcSrbTreeMap<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Key + Send + 'static, V: Send + 'static> FromIterator<(K, V)> for OlcSrbTreeMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut this = Self::new();
+        this.extend(iter);
+        this
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_insert_remove() {
+        let map = OlcSrbTreeMap::new();
+        let mut expected = BTreeMap::new();
+
+        for key in (0..5000u32).map(|k| k.wrapping_mul(2_654_435_761) % 100_000) {
+            assert_eq!(map.insert(key, key), expected.insert(key, key).is_none());
+        }
+        for key in (0..5000u32).step_by(3).map(|k| k * 20) {
+            assert_eq!(map.remove(&key), expected.remove(&key).is_some());
+        }
+
+        let guard = map.pin();
+        assert_eq!(map.len(), expected.len());
+        for (key, value) in &expected {
+            assert_eq!(map.get(key, &guard), Some((key, value)));
+        }
+        assert_eq!(map.get(&u32::MAX, &guard), None);
+        assert!(!map.remove(&u32::MAX));
+    }
+
+    #[test]
+    fn test_unlink() {
+        let map = OlcSrbTreeMap::new();
+        for key in (0..3000u32).map(|k| k * 97) {
+            map.insert(key, ());
+        }
+        for key in (0..3000u32).map(|k| k * 97) {
+            assert!(map.remove(&key));
+        }
+
+        // only the root is left, every node below it was emptied and unlinked
+        // SAFETY: no other thread uses the map
+        let root = unsafe { &*map.root.load(Ordering::Acquire) };
+        assert_eq!(root.len.load(Ordering::Relaxed), 0);
+        assert!(root
+            .children()
+            .iter()
+            .all(|child| child.load(Ordering::Relaxed).is_null()));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_reclaim() {
+        static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+        struct Counted;
+
+        impl Counted {
+            fn new() -> Self {
+                LIVE.fetch_add(1, Ordering::Relaxed);
+                Self
+            }
+        }
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                LIVE.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+
+        let map = Arc::new(OlcSrbTreeMap::new());
+        thread::scope(|scope| {
+            for writer in 0..4u32 {
+                let map = Arc::clone(&map);
+                scope.spawn(move || {
+                    for round in 0..50 {
+                        for key in (0..200).map(|k| k * 4 + writer) {
+                            map.insert(key, Counted::new());
+                            if round % 2 == 1 {
+                                map.remove(&key);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        // replaced and removed entries are freed while the map is still shared, other tests
+        // pinning threads only delay this
+        for _ in 0..10_000 {
+            if LIVE.load(Ordering::Relaxed) == 0 {
+                break;
+            }
+            map.pin().flush();
+            thread::yield_now();
+        }
+        assert_eq!(LIVE.load(Ordering::Relaxed), 0);
+        assert_eq!(Arc::strong_count(&map), 1);
+    }
+
+    #[test]
+    fn test_writers() {
+        let map = OlcSrbTreeMap::new();
+
+        thread::scope(|scope| {
+            for writer in 0..4u64 {
+                let map = &map;
+                scope.spawn(move || {
+                    // neighbouring keys of all writers share their leafs
+                    for key in (0..20_000).map(|k| k * 4 + writer) {
+                        map.insert(key, key);
+                        if key % 2 == 0 {
+                            assert!(map.remove(&key));
+                        }
+                        assert_eq!(map.contains_key(&key), key % 2 == 1);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(map.len(), 40_000);
+        assert!((0..80_000u64).all(|key| map.contains_key(&key) == (key % 2 == 1)));
+    }
+}
+
+/// Run with `RUSTFLAGS="--cfg loom" cargo test --release --lib olc`.
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use std::sync::Arc;
+
+    use loom::thread;
+
+    use super::*;
+
+    /// Runs `first` and `second` on two threads against a map holding `keys`, then checks it.
+    fn model<F, G, C>(keys: &'static [u8], first: F, second: G, check: C)
+    where
+        F: Fn(&OlcSrbTreeMap<u8, u8>) + Send + Sync + 'static,
+        G: Fn(&OlcSrbTreeMap<u8, u8>) + Send + Sync + 'static,
+        C: Fn(&OlcSrbTreeMap<u8, u8>) + Send + Sync + 'static,
+    {
+        let (first, second, check) = (Arc::new(first), Arc::new(second), Arc::new(check));
+        loom::model(move || {
+            let map = Arc::new(OlcSrbTreeMap::new());
+            for key in keys {
+                map.insert(*key, *key);
+            }
+
+            let other = {
+                let (map, second) = (Arc::clone(&map), Arc::clone(&second));
+                thread::spawn(move || second(&map))
+            };
+            first(&map);
+            other.join().unwrap();
+
+            check(&map);
+        });
+    }
+
+    #[test]
+    fn test_insert_same_leaf() {
+        model(
+            &[],
+            |map| assert!(map.insert(1, 1)),
+            |map| assert!(map.insert(2, 2)),
+            |map| {
+                assert_eq!(map.len(), 2);
+                assert_eq!(map.get(&1, &map.pin()), Some((&1, &1)));
+                assert_eq!(map.get(&2, &map.pin()), Some((&2, &2)));
+            },
+        );
+    }
+
+    #[test]
+    fn test_insert_remove_same_key() {
+        model(
+            &[16],
+            |map| {
+                map.insert(16, 0);
+            },
+            |map| {
+                map.remove(&16);
+            },
+            |map| match map.get(&16, &map.pin()) {
+                Some(entry) => assert_eq!((entry, map.len()), ((&16, &0), 1)),
+                None => assert_eq!(map.len(), 0),
+            },
+        );
+    }
+
+    #[test]
+    fn test_remove_unlinking_leaf() {
+        model(
+            &[16, 17],
+            |map| assert!(map.remove(&16)),
+            |map| assert!(map.remove(&17)),
+            |map| {
+                assert!(map.is_empty());
+                assert_eq!(map.get(&16, &map.pin()), None);
+                assert_eq!(map.get(&17, &map.pin()), None);
+            },
+        );
+    }
+
+    #[test]
+    fn test_insert_into_unlinked_leaf() {
+        model(
+            &[16],
+            |map| assert!(map.remove(&16)),
+            |map| {
+                assert!(map.insert(17, 17));
+                assert_eq!(map.get(&17, &map.pin()), Some((&17, &17)));
+            },
+            |map| {
+                assert_eq!(map.len(), 1);
+                assert_eq!(map.get(&16, &map.pin()), None);
+                assert_eq!(map.get(&17, &map.pin()), Some((&17, &17)));
+            },
+        );
+    }
+}
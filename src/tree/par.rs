@@ -0,0 +1,357 @@
+use std::slice;
+
+use allocator_api2::alloc::{Allocator, Global};
+use rayon::iter::plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer};
+use rayon::iter::ParallelIterator;
+
+use super::node::{Node, Repr};
+use super::storage::Storage;
+use super::B;
+
+/// Splits `nodes` where the entries below them are halved, a single internal node is replaced by
+/// its children first. Leafs are never split.
+fn split_nodes<T, F>(nodes: &mut Vec<T>, len: impl Fn(&T) -> usize, expand: F) -> Option<Vec<T>>
+where
+    F: Fn(T) -> Result<Vec<T>, T>,
+{
+    while nodes.len() == 1 {
+        match expand(nodes.pop().expect("one node is left")) {
+            Ok(children) => *nodes = children,
+            Err(leaf) => {
+                nodes.push(leaf);
+                return None;
+            }
+        }
+    }
+
+    if nodes.len() < 2 {
+        return None;
+    }
+
+    let total: usize = nodes.iter().map(&len).sum();
+    let mut below = 0;
+    let mid = nodes
+        .iter()
+        .position(|node| {
+            below += len(node);
+            2 * below >= total
+        })
+        .map_or(1, |idx| idx + 1)
+        .clamp(1, nodes.len() - 1);
+
+    Some(nodes.split_off(mid))
+}
+
+struct Producer<'a, K, V, A: Allocator + Clone> {
+    inline: slice::Iter<'a, Option<(K, V)>>,
+    /// Disjoint subtrees in key order.
+    nodes: Vec<&'a Node<K, V, B, A>>,
+}
+
+impl<'a, K, V, A: Allocator + Clone> Producer<'a, K, V, A> {
+    fn new(storage: &'a Storage<K, V, A>) -> Self {
+        match storage {
+            Storage::Inline(inline) => Self {
+                inline: inline.entries(),
+                nodes: Vec::new(),
+            },
+            Storage::Trie(trie) => Self {
+                inline: [].iter(),
+                nodes: vec![&trie.root],
+            },
+        }
+    }
+
+    fn fold_node<F: Folder<(&'a K, &'a V)>>(node: &'a Node<K, V, B, A>, mut folder: F) -> F {
+        match &node.repr {
+            Repr::Internal { children } => {
+                for (_, child) in children.iter() {
+                    folder = Self::fold_node(child, folder);
+                    if folder.full() {
+                        break;
+                    }
+                }
+            }
+            Repr::Leaf { entries } => {
+                for entry in entries.iter() {
+                    folder = folder.consume(entry);
+                    if folder.full() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        folder
+    }
+}
+
+impl<'a, K: Sync, V: Sync, A: Allocator + Clone + Sync> UnindexedProducer
+    for Producer<'a, K, V, A>
+{
+    type Item = (&'a K, &'a V);
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        let right = split_nodes(
+            &mut self.nodes,
+            |node| node.len,
+            |node| match &node.repr {
+                Repr::Internal { children } => Ok(children
+                    .iter()
+                    .map(|(_, child)| child)
+                    .filter(|child| child.len != 0)
+                    .collect()),
+                Repr::Leaf { .. } => Err(node),
+            },
+        );
+
+        let right = right.map(|nodes| Self {
+            inline: [].iter(),
+            nodes,
+        });
+        (self, right)
+    }
+
+    fn fold_with<F: Folder<Self::Item>>(self, mut folder: F) -> F {
+        folder = folder.consume_iter(self.inline.flatten().map(|(k, v)| (k, v)));
+        for node in self.nodes {
+            if folder.full() {
+                break;
+            }
+            folder = Self::fold_node(node, folder);
+        }
+
+        folder
+    }
+}
+
+struct ProducerMut<'a, K, V, A: Allocator + Clone> {
+    inline: slice::IterMut<'a, Option<(K, V)>>,
+    /// Disjoint subtrees in key order.
+    nodes: Vec<&'a mut Node<K, V, B, A>>,
+}
+
+impl<'a, K, V, A: Allocator + Clone> ProducerMut<'a, K, V, A> {
+    fn new(storage: &'a mut Storage<K, V, A>) -> Self {
+        match storage {
+            Storage::Inline(inline) => Self {
+                inline: inline.entries_mut(),
+                nodes: Vec::new(),
+            },
+            Storage::Trie(trie) => Self {
+                inline: [].iter_mut(),
+                nodes: vec![&mut trie.root],
+            },
+        }
+    }
+
+    fn fold_node<F: Folder<(&'a K, &'a mut V)>>(
+        node: &'a mut Node<K, V, B, A>,
+        mut folder: F,
+    ) -> F {
+        match &mut node.repr {
+            Repr::Internal { children } => {
+                for (_, child) in children.children_mut() {
+                    folder = Self::fold_node(child, folder);
+                    if folder.full() {
+                        break;
+                    }
+                }
+            }
+            Repr::Leaf { entries } => {
+                for entry in entries.iter_mut() {
+                    folder = folder.consume(entry);
+                    if folder.full() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        folder
+    }
+}
+
+impl<'a, K: Send + Sync, V: Send, A: Allocator + Clone + Send> UnindexedProducer
+    for ProducerMut<'a, K, V, A>
+{
+    type Item = (&'a K, &'a mut V);
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        let right = split_nodes(
+            &mut self.nodes,
+            |node| node.len,
+            |node| match node.repr {
+                Repr::Internal { ref mut children } => Ok(children
+                    .children_mut()
+                    .into_iter()
+                    .map(|(_, child)| child)
+                    .filter(|child| child.len != 0)
+                    .collect()),
+                Repr::Leaf { .. } => Err(node),
+            },
+        );
+
+        let right = right.map(|nodes| Self {
+            inline: [].iter_mut(),
+            nodes,
+        });
+        (self, right)
+    }
+
+    fn fold_with<F: Folder<Self::Item>>(self, mut folder: F) -> F {
+        folder = folder.consume_iter(self.inline.flatten().map(|(k, v)| (&*k, v)));
+        for node in self.nodes {
+            if folder.full() {
+                break;
+            }
+            folder = Self::fold_node(node, folder);
+        }
+
+        folder
+    }
+}
+
+/// A parallel iterator over the entries of a map, see [`super::map::SrbTreeMap::par_iter`].
+pub struct ParIter<'a, K, V, A: Allocator + Clone = Global> {
+    storage: &'a Storage<K, V, A>,
+}
+
+impl<'a, K, V, A: Allocator + Clone> ParIter<'a, K, V, A> {
+    pub(super) fn new(storage: &'a Storage<K, V, A>) -> Self {
+        Self { storage }
+    }
+}
+
+impl<'a, K: Sync, V: Sync, A: Allocator + Clone + Sync> ParallelIterator for ParIter<'a, K, V, A> {
+    type Item = (&'a K, &'a V);
+
+    fn drive_unindexed<C: UnindexedConsumer<Self::Item>>(self, consumer: C) -> C::Result {
+        bridge_unindexed(Producer::new(self.storage), consumer)
+    }
+}
+
+/// A parallel iterator over the entries of a map with mutable values, see
+/// [`super::map::SrbTreeMap::par_iter_mut`].
+pub struct ParIterMut<'a, K, V, A: Allocator + Clone = Global> {
+    storage: &'a mut Storage<K, V, A>,
+}
+
+impl<'a, K, V, A: Allocator + Clone> ParIterMut<'a, K, V, A> {
+    pub(super) fn new(storage: &'a mut Storage<K, V, A>) -> Self {
+        Self { storage }
+    }
+}
+
+impl<'a, K: Send + Sync, V: Send, A: Allocator + Clone + Send> ParallelIterator
+    for ParIterMut<'a, K, V, A>
+{
+    type Item = (&'a K, &'a mut V);
+
+    fn drive_unindexed<C: UnindexedConsumer<Self::Item>>(self, consumer: C) -> C::Result {
+        bridge_unindexed(ProducerMut::new(self.storage), consumer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelExtend};
+
+    use crate::tree::map::SrbTreeMap;
+    use crate::tree::set::SrbTreeSet;
+    use crate::tree::StorageMode;
+
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let map: SrbTreeMap<u32, u32> = (0..5000).map(|k| (k * 3, k)).collect();
+        let mut parts = vec![Producer::new(map.par_iter().storage)];
+        while parts.len() < 4 {
+            let (left, right) = parts.remove(0).split();
+            parts.push(left);
+            parts.extend(right);
+        }
+
+        // each quarter holds roughly a quarter of the entries
+        let mut lens: Vec<usize> = parts
+            .iter()
+            .map(|part| part.nodes.iter().map(|node| node.len).sum())
+            .collect();
+        assert_eq!(lens.iter().sum::<usize>(), 5000);
+        lens.sort();
+        assert!(lens[3] - lens[0] <= 5000 / 8, "{lens:?}");
+    }
+
+    #[test]
+    fn test_par_iter() {
+        for len in [0, 5, 100, 20_000] {
+            let mut map: SrbTreeMap<u32, u64> = (0..len).map(|k| (k * 7, k.into())).collect();
+
+            let entries: Vec<_> = map.par_iter().map(|(k, v)| (*k, *v)).collect();
+            assert!(entries.iter().map(|(k, v)| (k, v)).eq(map.iter()));
+            assert!(map
+                .par_keys()
+                .copied()
+                .collect::<Vec<_>>()
+                .iter()
+                .eq(map.keys()));
+            assert_eq!(map.par_values().sum::<u64>(), map.values().sum::<u64>());
+
+            map.par_iter_mut().for_each(|(k, v)| *v += u64::from(*k));
+            assert!(map.iter().all(|(k, v)| *v == u64::from(k / 7 + k)));
+
+            // short circuiting stops early and still finds an entry
+            assert_eq!(
+                map.par_keys().find_first(|k| **k % 10 == 1).copied(),
+                map.keys().find(|k| **k % 10 == 1).copied(),
+            );
+        }
+    }
+
+    #[test]
+    fn test_par_extend() {
+        let keys: Vec<u32> = (0..30_000u32)
+            .map(|k| k.wrapping_mul(2_654_435_761) >> 12)
+            .collect();
+        let mut model: BTreeMap<u32, usize> = (0..40).map(|k| (k * 1000, 0)).collect();
+        let mut map: SrbTreeMap<u32, usize> = model.clone().into_iter().collect();
+
+        map.par_extend(
+            keys.clone()
+                .into_par_iter()
+                .enumerate()
+                .map(|(v, k)| (k, v)),
+        );
+        model.extend(keys.iter().enumerate().map(|(v, k)| (*k, v)));
+        assert_eq!(map.len(), model.len());
+        assert!(map.iter().eq(model.iter()));
+
+        // a few pairs stay inline
+        let mut small = SrbTreeMap::new();
+        small.par_extend((0..3u8).into_par_iter().map(|k| (k, k)));
+        assert_eq!(small.storage_bytes().0, StorageMode::Inline);
+
+        let mut set = SrbTreeSet::with_universe(0xffffu16);
+        set.par_extend(
+            (0..0x10000u32)
+                .into_par_iter()
+                .map(|k| (k * 13 % 0x10000) as u16),
+        );
+        assert_eq!(set.len(), 0x10000);
+        assert!(set
+            .par_iter()
+            .copied()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .eq(0..=0xffff));
+
+        let result = std::panic::catch_unwind(move || {
+            let mut set = SrbTreeSet::with_universe(100u8);
+            set.par_extend([5u8, 200].into_par_iter());
+        });
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,423 @@
+use std::borrow::Borrow;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::key::Key;
+
+use super::B;
+
+#[derive(Clone)]
+enum PersistentNode<K, V> {
+    /// `len` is the number of children, not the number of entries below this node.
+    Internal {
+        len: usize,
+        children: [Option<Arc<PersistentNode<K, V>>>; B],
+    },
+    Leaf {
+        len: usize,
+        entries: [Option<(K, V)>; B],
+    },
+}
+
+impl<K, V> PersistentNode<K, V> {
+    fn is_empty(&self) -> bool {
+        match self {
+            PersistentNode::Internal { len, .. } | PersistentNode::Leaf { len, .. } => *len == 0,
+        }
+    }
+}
+
+/// An immutable map whose versions share all nodes they have in common.
+///
+/// Nodes are reference counted, so cloning a map only clones its root. Inserting or removing
+/// copies the nodes on the path to the key if they are shared with another version and leaves
+/// every other subtree shared.
+pub struct PersistentSrbTreeMap<K, V> {
+    root: Option<Arc<PersistentNode<K, V>>>,
+    height: usize,
+    len: usize,
+}
+
+impl<K, V> PersistentSrbTreeMap<K, V> {
+    pub const fn new() -> Self {
+        Self {
+            root: None,
+            height: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns whether both maps are the same version, i.e. share their root.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (&self.root, &other.root) {
+            (Some(root), Some(other)) => Arc::ptr_eq(root, other),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            stack: self.root.iter().map(|root| (&**root, 0)).collect(),
+            len: self.len,
+        }
+    }
+}
+
+impl<K, V> Clone for PersistentSrbTreeMap<K, V> {
+    /// Creates another handle to this version, this doesn't copy any nodes.
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            height: self.height,
+            len: self.len,
+        }
+    }
+}
+
+impl<K, V> Default for PersistentSrbTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Key, V> PersistentSrbTreeMap<K, V> {
+    fn depth(&self) -> usize {
+        K::max_depth(B) - self.height
+    }
+
+    pub fn get<Q: Borrow<K>>(&self, key: &Q) -> Option<(&K, &V)> {
+        let key = key.borrow();
+        if key.height(B) > self.height {
+            return None;
+        }
+
+        let mut node = self.root.as_deref()?;
+        for depth in self.depth()..=K::max_depth(B) {
+            let digit = key.index_at(B, depth);
+            match node {
+                PersistentNode::Internal { children, .. } => node = children[digit].as_deref()?,
+                PersistentNode::Leaf { entries, .. } => {
+                    return entries[digit].as_ref().map(|(k, v)| (k, v));
+                }
+            }
+        }
+
+        unreachable!("leafs are at max depth")
+    }
+
+    pub fn contains_key<Q: Borrow<K>>(&self, key: &Q) -> bool {
+        self.get(key).is_some()
+    }
+}
+
+impl<K: Key + Clone, V: Clone> PersistentSrbTreeMap<K, V> {
+    fn node_at(depth: usize) -> PersistentNode<K, V> {
+        if depth == K::max_depth(B) {
+            PersistentNode::Leaf {
+                len: 0,
+                entries: [const { None }; B],
+            }
+        } else {
+            PersistentNode::Internal {
+                len: 0,
+                children: [const { None }; B],
+            }
+        }
+    }
+
+    /// Adds levels above the root until it is at least at the given height.
+    fn grow(&mut self, height: usize) {
+        let Some(mut root) = self.root.take() else {
+            // the root is created at the right depth on the next insert
+            self.height = self.height.max(height);
+            return;
+        };
+
+        while self.height < height {
+            let mut children = [const { None }; B];
+            children[0] = Some(root);
+            root = Arc::new(PersistentNode::Internal { len: 1, children });
+            self.height += 1;
+        }
+
+        self.root = Some(root);
+    }
+
+    /// Removes levels above the root for as long as all entries are below its first child, an
+    /// empty root is dropped entirely.
+    fn shrink(&mut self) {
+        while let Some(root) = &self.root {
+            match &**root {
+                node if node.is_empty() => {
+                    self.root = None;
+                    self.height = 0;
+                }
+                PersistentNode::Internal {
+                    len: 1,
+                    children: [Some(first), ..],
+                } => {
+                    self.root = Some(Arc::clone(first));
+                    self.height -= 1;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Returns a new version holding the given pair in addition to the entries of this one.
+    pub fn insert(&self, key: K, value: V) -> Self {
+        let mut new = self.clone();
+        new.insert_mut(key, value);
+        new
+    }
+
+    /// Returns a new version without the given key.
+    pub fn remove<Q: Borrow<K>>(&self, key: &Q) -> Self {
+        let mut new = self.clone();
+        new.remove_mut(key);
+        new
+    }
+
+    /// Inserts a key value pair into this version, returning the previous pair for this key.
+    ///
+    /// Nodes on the path to the key are copied if another version holds them, nodes only held by
+    /// this version are updated in place.
+    pub fn insert_mut(&mut self, key: K, value: V) -> Option<(K, V)> {
+        self.grow(key.height(B));
+
+        let mut depth = self.depth();
+        let root = self
+            .root
+            .get_or_insert_with(|| Arc::new(Self::node_at(depth)));
+        let mut node = Arc::make_mut(root);
+
+        loop {
+            let digit = key.index_at(B, depth);
+            match node {
+                PersistentNode::Internal { len, children } => {
+                    let child = children[digit].get_or_insert_with(|| {
+                        *len += 1;
+                        Arc::new(Self::node_at(depth + 1))
+                    });
+                    node = Arc::make_mut(child);
+                }
+                PersistentNode::Leaf { len, entries } => {
+                    let old = entries[digit].replace((key, value));
+                    if old.is_none() {
+                        *len += 1;
+                        self.len += 1;
+                    }
+                    return old;
+                }
+            }
+
+            depth += 1;
+        }
+    }
+
+    fn remove_at(node: &mut Arc<PersistentNode<K, V>>, depth: usize, key: &K) -> Option<(K, V)> {
+        let digit = key.index_at(B, depth);
+        match Arc::make_mut(node) {
+            PersistentNode::Internal { len, children } => {
+                let child = children[digit].as_mut()?;
+                let old = Self::remove_at(child, depth + 1, key);
+
+                if child.is_empty() {
+                    children[digit] = None;
+                    *len -= 1;
+                }
+
+                old
+            }
+            PersistentNode::Leaf { len, entries } => {
+                let old = entries[digit].take();
+                if old.is_some() {
+                    *len -= 1;
+                }
+                old
+            }
+        }
+    }
+
+    /// Removes a key from this version, returning the removed pair.
+    ///
+    /// Like [`Self::insert_mut`] this only copies shared nodes, and none at all if the key is
+    /// absent.
+    pub fn remove_mut<Q: Borrow<K>>(&mut self, key: &Q) -> Option<(K, V)> {
+        let key = key.borrow();
+        if !self.contains_key(key) {
+            return None;
+        }
+
+        let depth = self.depth();
+        let old = Self::remove_at(self.root.as_mut()?, depth, key);
+        if old.is_some() {
+            self.len -= 1;
+            self.shrink();
+        }
+
+        old
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for PersistentSrbTreeMap<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Key + Clone, V: Clone> Extend<(K, V)> for PersistentSrbTreeMap<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert_mut(key, value);
+        }
+    }
+}
+
+impl<K: Key + Clone, V: Clone> FromIterator<(K, V)> for PersistentSrbTreeMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut this = Self::new();
+        this.extend(iter);
+        this
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    /// The nodes on the path to the next entry and the next digit to visit in each of them.
+    stack: Vec<(&'a PersistentNode<K, V>, usize)>,
+    len: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, digit) = self.stack.last_mut()?;
+            if *digit == B {
+                self.stack.pop();
+                continue;
+            }
+
+            let (node, current) = (*node, *digit);
+            *digit += 1;
+
+            match node {
+                PersistentNode::Internal { children, .. } => {
+                    if let Some(child) = &children[current] {
+                        self.stack.push((child, 0));
+                    }
+                }
+                PersistentNode::Leaf { entries, .. } => {
+                    if let Some((k, v)) = &entries[current] {
+                        self.len -= 1;
+                        return Some((k, v));
+                    }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn children<K, V>(map: &PersistentSrbTreeMap<K, V>) -> &[Option<Arc<PersistentNode<K, V>>>] {
+        match map.root.as_deref() {
+            Some(PersistentNode::Internal { children, .. }) => children,
+            _ => &[],
+        }
+    }
+
+    #[test]
+    fn test_versions() {
+        let mut versions = vec![PersistentSrbTreeMap::new()];
+        let mut expected = vec![BTreeMap::new()];
+
+        for key in (0..3000u32).map(|k| k.wrapping_mul(2_654_435_761) % 50_000) {
+            let mut map = versions.last().unwrap().clone();
+            let mut model = expected.last().unwrap().clone();
+            if key % 3 == 0 {
+                map = map.remove(&(key / 2));
+                model.remove(&(key / 2));
+            } else {
+                map = map.insert(key, key);
+                model.insert(key, key);
+            }
+            versions.push(map);
+            expected.push(model);
+        }
+
+        for (map, model) in versions.iter().zip(&expected) {
+            assert_eq!(map.len(), model.len());
+            assert!(map.iter().eq(model.iter()));
+        }
+    }
+
+    #[test]
+    fn test_structural_sharing() {
+        let old: PersistentSrbTreeMap<u32, u32> = (0..4096).map(|k| (k, k)).collect();
+        let new = old.insert(4000, 0).remove(&17);
+
+        assert!(!new.ptr_eq(&old));
+        assert_eq!(old.get(&17), Some((&17, &17)));
+        assert_eq!(new.get(&17), None);
+
+        // only the first and the last child of the root hold changed keys
+        let shared = children(&old)
+            .iter()
+            .zip(children(&new))
+            .filter(|(old, new)| match (old, new) {
+                (Some(old), Some(new)) => Arc::ptr_eq(old, new),
+                _ => false,
+            })
+            .count();
+        assert_eq!(shared, 14);
+
+        let copy = new.clone();
+        assert!(copy.ptr_eq(&new));
+    }
+
+    #[test]
+    fn test_copy_on_write() {
+        let mut map: PersistentSrbTreeMap<u64, String> =
+            (0..300).map(|k| (k * 7, k.to_string())).collect();
+        let snapshot = map.clone();
+
+        assert_eq!(map.remove_mut(&1), None);
+        assert!(map.ptr_eq(&snapshot));
+
+        map.insert_mut(14, "changed".to_string());
+        map.remove_mut(&21);
+        map.insert_mut(1 << 40, "far".to_string());
+
+        assert_eq!(snapshot.get(&14), Some((&14, &"2".to_string())));
+        assert_eq!(snapshot.get(&21), Some((&21, &"3".to_string())));
+        assert_eq!(snapshot.len(), 300);
+        assert_eq!(map.get(&14), Some((&14, &"changed".to_string())));
+        assert_eq!(map.len(), 300);
+
+        // once unshared, nodes are updated in place
+        let root = map.root.as_ref().map(Arc::as_ptr);
+        map.insert_mut(15, "new".to_string());
+        assert_eq!(map.root.as_ref().map(Arc::as_ptr), root);
+    }
+}
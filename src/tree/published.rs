@@ -0,0 +1,216 @@
+use std::borrow::Borrow;
+use std::sync::Arc;
+
+use arc_swap::{ArcSwap, Guard};
+
+use crate::key::Key;
+
+use super::map::SrbTreeMap;
+use super::persistent::PersistentSrbTreeMap;
+
+/// The writing half of a map for read-mostly workloads, readers only see the changes made
+/// through it once they are published.
+///
+/// Changes go to a private version of a [`PersistentSrbTreeMap`] which shares all untouched
+/// nodes with the published one. Publishing swaps a pointer, so it takes constant time and
+/// readers never wait for the writer or for each other.
+pub struct SrbTreeMapWriter<K, V> {
+    draft: PersistentSrbTreeMap<K, V>,
+    published: Arc<ArcSwap<PersistentSrbTreeMap<K, V>>>,
+}
+
+/// The reading half of a map written through a [`SrbTreeMapWriter`], cloning it is cheap.
+pub struct SrbTreeMapReader<K, V> {
+    published: Arc<ArcSwap<PersistentSrbTreeMap<K, V>>>,
+}
+
+impl<K, V> SrbTreeMapWriter<K, V> {
+    pub fn new() -> Self {
+        Self::from_persistent(PersistentSrbTreeMap::new())
+    }
+
+    /// Publishes the given version right away.
+    pub fn from_persistent(map: PersistentSrbTreeMap<K, V>) -> Self {
+        Self {
+            draft: map.clone(),
+            published: Arc::new(ArcSwap::from_pointee(map)),
+        }
+    }
+
+    /// Returns a new reader of the published version.
+    pub fn reader(&self) -> SrbTreeMapReader<K, V> {
+        SrbTreeMapReader {
+            published: Arc::clone(&self.published),
+        }
+    }
+
+    /// Returns the version the next [`Self::publish`] makes visible, including all changes made
+    /// so far.
+    pub fn draft(&self) -> &PersistentSrbTreeMap<K, V> {
+        &self.draft
+    }
+
+    /// Returns whether there are changes readers can't see yet.
+    pub fn is_dirty(&self) -> bool {
+        !self.draft.ptr_eq(&self.published.load())
+    }
+
+    /// Makes all changes made so far visible to readers.
+    ///
+    /// Readers holding the previous version keep it until they drop it, the nodes it shares
+    /// with the new version aren't copied.
+    pub fn publish(&mut self) {
+        if self.is_dirty() {
+            self.published.store(Arc::new(self.draft.clone()));
+        }
+    }
+}
+
+impl<K: Key + Clone, V: Clone> SrbTreeMapWriter<K, V> {
+    /// Publishes a copy of the entries of `map` right away.
+    pub fn from_map(map: &SrbTreeMap<K, V>) -> Self {
+        Self::from_persistent(map.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+    }
+
+    /// Inserts a key value pair without publishing it, returning the previous pair for this key.
+    ///
+    /// The first change to a node after a publish copies it, later ones update it in place.
+    pub fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        self.draft.insert_mut(key, value)
+    }
+
+    /// Removes a key without publishing the removal, returning the removed pair.
+    pub fn remove<Q: Borrow<K>>(&mut self, key: &Q) -> Option<(K, V)> {
+        self.draft.remove_mut(key)
+    }
+}
+
+impl<K, V> Default for SrbTreeMapWriter<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Key + Clone, V: Clone> Extend<(K, V)> for SrbTreeMapWriter<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.draft.extend(iter);
+    }
+}
+
+impl<K, V> SrbTreeMapReader<K, V> {
+    /// Returns the published version for a few lookups.
+    ///
+    /// This doesn't touch the reference count in the common case, but a writer can't free the
+    /// version while the guard is alive, so it should be short lived. Use [`Self::snapshot`] to
+    /// hold on to a version.
+    pub fn load(&self) -> Guard<Arc<PersistentSrbTreeMap<K, V>>> {
+        self.published.load()
+    }
+
+    /// Returns the published version, it stays unchanged no matter what is published later.
+    pub fn snapshot(&self) -> Arc<PersistentSrbTreeMap<K, V>> {
+        self.published.load_full()
+    }
+
+    pub fn len(&self) -> usize {
+        self.load().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.load().is_empty()
+    }
+}
+
+impl<K: Key, V> SrbTreeMapReader<K, V> {
+    pub fn get<Q: Borrow<K>>(&self, key: &Q) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.load().get(key).map(|(_, v)| v.clone())
+    }
+
+    pub fn contains_key<Q: Borrow<K>>(&self, key: &Q) -> bool {
+        self.load().contains_key(key)
+    }
+}
+
+impl<K, V> Clone for SrbTreeMapReader<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            published: Arc::clone(&self.published),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_publish() {
+        let mut writer = SrbTreeMapWriter::from_map(&(0..100u32).map(|k| (k, k)).collect());
+        let reader = writer.reader();
+        let before = reader.snapshot();
+
+        writer.insert(1000, 0);
+        writer.remove(&5);
+        assert!(writer.is_dirty());
+        assert_eq!(writer.draft().len(), 100);
+
+        // nothing is visible before publishing
+        assert_eq!(reader.get(&1000), None);
+        assert_eq!(reader.get(&5), Some(5));
+
+        writer.publish();
+        assert!(!writer.is_dirty());
+        assert_eq!(reader.get(&1000), Some(0));
+        assert_eq!(reader.get(&5), None);
+        assert_eq!(reader.clone().len(), 100);
+
+        // an older snapshot is left as is
+        assert_eq!(before.get(&5), Some((&5, &5)));
+        assert_eq!(before.len(), 100);
+
+        // publishing without changes keeps the version
+        let current = reader.snapshot();
+        writer.publish();
+        assert!(Arc::ptr_eq(&current, &reader.snapshot()));
+    }
+
+    #[test]
+    fn test_readers() {
+        let mut writer = SrbTreeMapWriter::new();
+        let done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                let reader = writer.reader();
+                let done = &done;
+                scope.spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        // keys are published in batches of ten, in order
+                        let map = reader.load();
+                        let len = map.len() as u64;
+                        assert_eq!(len % 10, 0);
+                        for key in 0..len {
+                            assert_eq!(map.get(&(key * 3)), Some((&(key * 3), &key)));
+                        }
+                    }
+                });
+            }
+
+            for key in 0..2000u64 {
+                writer.insert(key * 3, key);
+                if key % 10 == 9 {
+                    writer.publish();
+                }
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+
+        assert_eq!(writer.reader().len(), 2000);
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use allocator_api2::alloc::Allocator;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use crate::key::Key;
+
+use super::map::SrbTreeMap;
+use super::set::SrbTreeSet;
+
+/// Deserializes a collection like its own `Deserialize` impl, but fails on duplicate keys instead
+/// of keeping the last value for them.
+///
+/// ```ignore
+/// let Strict(map) = serde_json::from_str::<Strict<SrbTreeMap<u32, u32>>>(json)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Strict<T>(pub T);
+
+impl<T> Strict<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<K: Serialize, V: Serialize, A: Allocator + Clone> Serialize for SrbTreeMap<K, V, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (key, value) in self.iter() {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<T: Key + Serialize, A: Allocator + Clone> Serialize for SrbTreeSet<T, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for value in self.iter() {
+            seq.serialize_element(value)?;
+        }
+        seq.end()
+    }
+}
+
+/// Builds a map from the entries returned by `next`.
+///
+/// Entries go through [`SrbTreeMap::from_sorted_iter`] for as long as their keys ascend, the
+/// first entry which doesn't and all entries after it are inserted one by one.
+fn build<K, V, E, F>(mut next: F, strict: bool) -> Result<SrbTreeMap<K, V>, E>
+where
+    K: Key,
+    E: de::Error,
+    F: FnMut() -> Result<Option<(K, V)>, E>,
+{
+    let mut index = 0;
+    let mut last = None;
+    // the entry which ended the ascending run, or the error reading it
+    let mut stop = None;
+
+    let ascending = std::iter::from_fn(|| match next() {
+        Ok(Some((key, value))) if last.is_none_or(|last| key.to_usize() > last) => {
+            last = Some(key.to_usize());
+            index += 1;
+            Some((key, value))
+        }
+        Ok(None) => None,
+        other => {
+            stop = Some(other);
+            None
+        }
+    });
+    let mut map = SrbTreeMap::from_sorted_iter(ascending).expect("keys are strictly ascending");
+
+    let mut entry = match stop {
+        None => return Ok(map),
+        Some(entry) => entry?,
+    };
+    while let Some((key, value)) = entry {
+        if map.insert(key, value).is_some() && strict {
+            return Err(E::custom(format_args!("duplicate key at position {index}")));
+        }
+
+        index += 1;
+        entry = next()?;
+    }
+
+    Ok(map)
+}
+
+struct MapVisitor<K, V> {
+    strict: bool,
+    marker: PhantomData<(K, V)>,
+}
+
+impl<'de, K: Key + Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for MapVisitor<K, V> {
+    type Value = SrbTreeMap<K, V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<M: MapAccess<'de>>(self, mut access: M) -> Result<Self::Value, M::Error> {
+        build(|| access.next_entry(), self.strict)
+    }
+}
+
+struct SetVisitor<T> {
+    strict: bool,
+    marker: PhantomData<T>,
+}
+
+impl<'de, T: Key + Deserialize<'de>> Visitor<'de> for SetVisitor<T> {
+    type Value = SrbTreeSet<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a sequence")
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut access: S) -> Result<Self::Value, S::Error> {
+        let next = || Ok(access.next_element()?.map(|value| (value, ())));
+        build(next, self.strict).map(SrbTreeSet::from_map)
+    }
+}
+
+impl<'de, K: Key + Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for SrbTreeMap<K, V> {
+    /// Pairs with equal keys replace each other, see [`Strict`] for failing on them instead.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(MapVisitor {
+            strict: false,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<'de, T: Key + Deserialize<'de>> Deserialize<'de> for SrbTreeSet<T> {
+    /// Equal values are only kept once, see [`Strict`] for failing on them instead.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(SetVisitor {
+            strict: false,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<'de, K: Key + Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de>
+    for Strict<SrbTreeMap<K, V>>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let visitor = MapVisitor {
+            strict: true,
+            marker: PhantomData,
+        };
+        deserializer.deserialize_map(visitor).map(Strict)
+    }
+}
+
+impl<'de, T: Key + Deserialize<'de>> Deserialize<'de> for Strict<SrbTreeSet<T>> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let visitor = SetVisitor {
+            strict: true,
+            marker: PhantomData,
+        };
+        deserializer.deserialize_seq(visitor).map(Strict)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let map: SrbTreeMap<u32, String> = (0..500).map(|k| (k * 37, k.to_string())).collect();
+        let json = serde_json::to_string(&map).unwrap();
+        assert!(json.starts_with(r#"{"0":"0","37":"1","#));
+
+        let back: SrbTreeMap<u32, String> = serde_json::from_str(&json).unwrap();
+        assert!(back.iter().eq(map.iter()));
+
+        let set: SrbTreeSet<u16> = [5, 1, 300].into_iter().collect();
+        assert_eq!(serde_json::to_string(&set).unwrap(), "[1,5,300]");
+    }
+
+    #[test]
+    fn test_bincode() {
+        let set: SrbTreeSet<u64> = (0..10_000).map(|v| v * v).collect();
+        let bytes = bincode::serialize(&set).unwrap();
+        let back: SrbTreeSet<u64> = bincode::deserialize(&bytes).unwrap();
+        assert!(back.iter().eq(set.iter()));
+        assert_eq!(back.stats(), set.stats());
+    }
+
+    #[test]
+    fn test_unordered() {
+        // the ascending prefix is built in bulk, the rest is inserted
+        let map: SrbTreeMap<u8, u8> =
+            serde_json::from_str(r#"{"1":1,"2":2,"9":9,"3":3,"200":200,"0":0}"#).unwrap();
+        assert!(map.keys().copied().eq([0, 1, 2, 3, 9, 200]));
+
+        let set: SrbTreeSet<u32> = serde_json::from_str("[3,2,1,2,3]").unwrap();
+        assert!(set.iter().copied().eq([1, 2, 3]));
+
+        let map: SrbTreeMap<u8, u8> = serde_json::from_str(r#"{"1":1,"1":2}"#).unwrap();
+        assert_eq!(map.get(&1), Some((&1, &2)));
+    }
+
+    #[test]
+    fn test_strict() {
+        let Strict(map) =
+            serde_json::from_str::<Strict<SrbTreeMap<u8, u8>>>(r#"{"2":1,"1":2}"#).unwrap();
+        assert_eq!(map.len(), 2);
+
+        let err = serde_json::from_str::<Strict<SrbTreeMap<u8, u8>>>(r#"{"1":1,"2":1,"1":2}"#)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("duplicate key at position 2"),
+            "{err}"
+        );
+
+        let err = serde_json::from_str::<Strict<SrbTreeSet<u32>>>("[4,4]").unwrap_err();
+        assert!(
+            err.to_string().contains("duplicate key at position 1"),
+            "{err}"
+        );
+
+        // errors of the format are passed on
+        assert!(serde_json::from_str::<Strict<SrbTreeSet<u8>>>("[1,300]").is_err());
+        assert!(serde_json::from_str::<SrbTreeSet<u8>>("[3,1,-1]").is_err());
+    }
+}
@@ -1,68 +1,225 @@
+use std::ops::RangeBounds;
 use std::{borrow::Borrow, fmt::Debug};
 
+use allocator_api2::alloc::{Allocator, Global};
+#[cfg(feature = "rayon")]
+use rayon::iter::{IntoParallelIterator, ParallelExtend, ParallelIterator};
+
 use crate::key::Key;
 
-use super::node::{self, Node};
-use super::B;
+use super::chunks::Chunk;
+use super::error::{BuildError, InsertError};
+use super::frozen;
+use super::map::{self, SrbTreeMap};
+use super::{StorageMode, TreeStats};
 
-pub struct SrbTreeSet<V> {
-    root: Node<V, (), B>,
+/// An ordered set of integers, an [`SrbTreeMap`] without values.
+///
+/// Like the map it is `Send` and `Sync` whenever its values and allocator are.
+pub struct SrbTreeSet<T, A: Allocator + Clone = Global> {
+    map: SrbTreeMap<T, (), A>,
 }
 
 impl<T> SrbTreeSet<T> {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
+        Self {
+            map: SrbTreeMap::new(),
+        }
+    }
+
+    /// Creates a set which only accepts values up to and including `max`, see
+    /// [`SrbTreeMap::with_universe`].
+    pub const fn with_universe(max: T) -> Self {
+        Self {
+            map: SrbTreeMap::with_universe(max),
+        }
+    }
+}
+
+impl<T, A: Allocator + Clone> SrbTreeSet<T, A> {
+    /// Creates a set which allocates its nodes in the given allocator.
+    pub const fn new_in(alloc: A) -> Self {
+        Self {
+            map: SrbTreeMap::new_in(alloc),
+        }
+    }
+
+    /// Creates a set in the given allocator which only accepts values up to and including `max`.
+    pub const fn with_universe_in(max: T, alloc: A) -> Self {
         Self {
-            root: Node::new_internal(),
+            map: SrbTreeMap::with_universe_in(max, alloc),
         }
     }
 
-    pub fn storage_bytes(&self) -> usize {
-        self.root.storage_bytes()
+    #[cfg(feature = "serde")]
+    pub(super) fn from_map(map: SrbTreeMap<T, (), A>) -> Self {
+        Self { map }
+    }
+
+    pub fn allocator(&self) -> &A {
+        self.map.allocator()
+    }
+
+    /// Removes all values, see [`SrbTreeMap::clear`].
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    pub fn set_pool_limit(&mut self, limit: usize) {
+        self.map.set_pool_limit(limit);
+    }
+
+    pub fn pool_size(&self) -> (usize, usize) {
+        self.map.pool_size()
+    }
+
+    pub fn shrink_pool(&mut self) {
+        self.map.shrink_pool();
+    }
+
+    /// Returns the largest value the set accepts if it was created with [`Self::with_universe`],
+    /// use [`Self::last`] for the largest value it holds.
+    pub fn max_key(&self) -> Option<&T> {
+        self.map.max_key()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn storage_bytes(&self) -> (StorageMode, usize) {
+        self.map.storage_bytes()
     }
 
     pub fn storage(&self) -> f32 {
-        let (total, used) = self.root.storage_util();
-        used as f32 / total as f32
+        self.map.storage()
+    }
+
+    pub fn stats(&self) -> TreeStats {
+        self.map.stats()
+    }
+}
+
+impl<T> Default for SrbTreeSet<T> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl<T: Key> SrbTreeSet<T> {
+    /// Creates a set from strictly ascending values, see [`SrbTreeMap::from_sorted_iter_in`].
+    pub fn from_sorted_iter<I: IntoIterator<Item = T>>(iter: I) -> Result<Self, BuildError> {
+        Self::from_sorted_iter_in(iter, Global)
+    }
+}
+
+impl<T: Key, A: Allocator + Clone> SrbTreeSet<T, A> {
+    /// Creates a set in the given allocator from strictly ascending values.
+    pub fn from_sorted_iter_in<I>(iter: I, alloc: A) -> Result<Self, BuildError>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        Ok(Self {
+            map: SrbTreeMap::from_sorted_iter_in(iter.into_iter().map(|value| (value, ())), alloc)?,
+        })
+    }
+
     pub fn insert(&mut self, value: T) -> Option<T> {
-        self.root.insert(0, value, ()).map(|(v, _)| v)
+        self.map.insert(value, ()).map(|(v, _)| v)
+    }
+
+    pub fn try_insert(&mut self, value: T) -> Result<Option<T>, InsertError> {
+        self.map
+            .try_insert(value, ())
+            .map(|old| old.map(|(v, _)| v))
+    }
+
+    /// Allocates everything needed to insert the given value, see
+    /// [`SrbTreeMap::try_reserve_path`].
+    pub fn try_reserve_path(&mut self, value: &T) -> Result<(), InsertError> {
+        self.map.try_reserve_path(value)
+    }
+
+    /// Allocates all nodes holding the values in the given range, see
+    /// [`SrbTreeMap::reserve_range`].
+    pub fn reserve_range<R: RangeBounds<T>>(&mut self, range: R) {
+        self.map.reserve_range(range);
+    }
+
+    /// Inserts all values of `iter`, returning how many of them were new, see
+    /// [`SrbTreeMap::insert_batch`].
+    pub fn insert_batch<I: IntoIterator<Item = T>>(&mut self, iter: I) -> usize {
+        self.map
+            .insert_batch(iter.into_iter().map(|value| (value, ())))
+    }
+
+    /// Inserts ascending values, see [`SrbTreeMap::extend_sorted`].
+    pub fn extend_sorted<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.insert_batch(iter);
     }
 
     pub fn remove<Q: Borrow<T>>(&mut self, value: &Q) -> Option<T> {
-        self.root.remove(0, value.borrow()).map(|(v, _)| v)
+        self.map.remove(value).map(|(v, _)| v)
     }
 
     pub fn get<Q: Borrow<T>>(&self, value: &Q) -> Option<&T> {
-        self.root.get(0, value.borrow()).map(|(v, _)| v)
+        self.map.get(value).map(|(v, _)| v)
     }
 
     pub fn first(&self) -> Option<&T> {
-        self.root.first().map(|(v, _)| v)
+        self.map.first().map(|(v, _)| v)
     }
 
     pub fn last(&self) -> Option<&T> {
-        self.root.last().map(|(v, _)| v)
+        self.map.last().map(|(v, _)| v)
+    }
+
+    pub fn iter(&self) -> map::Keys<'_, T, (), A> {
+        self.map.keys()
     }
 
-    pub fn iter(&self) -> node::iter::Keys<'_, T, (), B> {
-        self.root.keys()
+    /// Splits the values into `n` consecutive chunks of about the same length, see
+    /// [`SrbTreeMap::chunks`]. The values of a chunk are its keys.
+    pub fn chunks(&self, n: usize) -> Vec<Chunk<'_, T, (), A>> {
+        self.map.chunks(n)
+    }
+
+    /// Writes the set into a compact, position independent format which
+    /// [`FrozenSrbTreeSet`] reads in place, for example from a memory mapped file.
+    ///
+    /// Values are stored as the `u64` of their [`Key::to_usize`] value and nodes address their
+    /// children by offsets into the bytes instead of pointers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a value doesn't fit into a `u64` or the frozen set doesn't fit into 4 GiB.
+    ///
+    /// [`FrozenSrbTreeSet`]: super::frozen::FrozenSrbTreeSet
+    pub fn freeze(&self) -> Vec<u8> {
+        let entries: Vec<_> = self.iter().map(|v| (frozen::key(v), &[][..])).collect();
+        frozen::freeze(&entries, frozen::Kind::Set)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<T: Sync, A: Allocator + Clone + Sync> SrbTreeSet<T, A> {
+    /// Returns a parallel iterator over the values, see [`SrbTreeMap::par_iter`].
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = &T> + '_ {
+        self.map.par_keys()
     }
 }
 
-impl<T: Debug> Debug for SrbTreeSet<T> {
+impl<T: Debug, A: Allocator + Clone> Debug for SrbTreeSet<T, A> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut set = f.debug_set();
-        self.root.pairs().for_each(|(v, _)| {
-            set.entry(v);
-        });
-        set.finish()
+        f.debug_set().entries(self.map.keys()).finish()
     }
 }
 
-impl<T: Key> Extend<T> for SrbTreeSet<T> {
+impl<T: Key, A: Allocator + Clone> Extend<T> for SrbTreeSet<T, A> {
     fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
         for item in iter {
             self.insert(item);
@@ -70,6 +227,15 @@ impl<T: Key> Extend<T> for SrbTreeSet<T> {
     }
 }
 
+#[cfg(feature = "rayon")]
+impl<T: Key + Send, A: Allocator + Clone + Send> ParallelExtend<T> for SrbTreeSet<T, A> {
+    /// Inserts all values, see [`SrbTreeMap::par_extend`].
+    fn par_extend<I: IntoParallelIterator<Item = T>>(&mut self, iter: I) {
+        self.map
+            .par_extend(iter.into_par_iter().map(|value| (value, ())));
+    }
+}
+
 impl<T: Key> FromIterator<T> for SrbTreeSet<T> {
     fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
         let mut this = Self::new();
@@ -0,0 +1,271 @@
+use std::array;
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::key::Key;
+
+use super::error::InsertError;
+use super::map::SrbTreeMap;
+use super::B;
+
+/// A map for concurrent writers which puts every child of the root behind its own lock.
+///
+/// Keys are split into `B` shards by their top digit, so writers to disjoint key ranges don't
+/// contend. Each shard is an [`SrbTreeMap`] of its own. Maps created with [`Self::new_hashed`]
+/// pick the shard from all digits of a key instead.
+pub struct ShardedSrbTreeMap<K, V> {
+    shards: [RwLock<SrbTreeMap<K, V>>; B],
+    /// The depth of the digit which picks the shard, all digits are folded into one if there is
+    /// none.
+    depth: Option<usize>,
+    max_key: Option<K>,
+}
+
+impl<K: Key, V> ShardedSrbTreeMap<K, V> {
+    /// Creates a map sharded by the top digit of the key type.
+    pub fn new() -> Self {
+        Self {
+            shards: array::from_fn(|_| RwLock::new(SrbTreeMap::new())),
+            depth: Some(0),
+            max_key: None,
+        }
+    }
+
+    /// Creates a map sharded by the digits of each key folded into one.
+    ///
+    /// Keys which only differ in any one digit land in different shards, so sequential or
+    /// strided keys are spread over all shards no matter how small they are. Writers to a key
+    /// range contend with all other writers in turn, and [`Self::snapshot`] has to sort.
+    pub fn new_hashed() -> Self {
+        Self {
+            shards: array::from_fn(|_| RwLock::new(SrbTreeMap::new())),
+            depth: None,
+            max_key: None,
+        }
+    }
+
+    /// Creates a map which only accepts keys up to and including `max_key`, sharded by the top
+    /// digit of `max_key` so that small keys are spread over all shards.
+    pub fn with_universe(max_key: K) -> Self {
+        Self {
+            shards: array::from_fn(|_| RwLock::new(SrbTreeMap::new())),
+            depth: Some(K::max_depth(B) - max_key.height(B)),
+            max_key: Some(max_key),
+        }
+    }
+
+    fn shard(&self, key: &K) -> &RwLock<SrbTreeMap<K, V>> {
+        let idx = match self.depth {
+            Some(depth) => key.index_at(B, depth),
+            None => (0..=K::max_depth(B)).fold(0, |idx, depth| idx ^ key.index_at(B, depth)),
+        };
+        &self.shards[idx]
+    }
+
+    /// A panic while a shard was locked leaves its tree intact, so poisoned locks are recovered.
+    fn read(shard: &RwLock<SrbTreeMap<K, V>>) -> RwLockReadGuard<'_, SrbTreeMap<K, V>> {
+        shard.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(shard: &RwLock<SrbTreeMap<K, V>>) -> RwLockWriteGuard<'_, SrbTreeMap<K, V>> {
+        shard.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn assert_range(&self, key: &K) {
+        if self.max_key.as_ref().is_some_and(|max| key > max) {
+            panic!("{}", InsertError::OutOfRange);
+        }
+    }
+
+    /// Returns the number of entries, shards are counted one after another so this may be stale
+    /// if other threads are writing.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| Self::read(shard).len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        Self::read(self.shard(key)).get(key).map(|(_, v)| v.clone())
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        Self::read(self.shard(key)).get(key).is_some()
+    }
+
+    /// Inserts a key value pair, returning the previous value for this key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is outside of the map's universe.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.assert_range(&key);
+        Self::write(self.shard(&key))
+            .insert(key, value)
+            .map(|(_, v)| v)
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        Self::write(self.shard(key)).remove(key).map(|(_, v)| v)
+    }
+
+    /// Updates the value of `key` in place, inserting `default()` first if the key is absent.
+    ///
+    /// Only the key's shard is locked while `f` runs, so `f` must not access the same shard.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is outside of the map's universe.
+    pub fn update_with<D, F, R>(&self, key: K, default: D, f: F) -> R
+    where
+        D: FnOnce() -> V,
+        F: FnOnce(&mut V) -> R,
+    {
+        self.assert_range(&key);
+        let mut shard = Self::write(self.shard(&key));
+        if let Some((_, value)) = shard.get_mut(&key) {
+            return f(value);
+        }
+
+        let mut value = default();
+        let result = f(&mut value);
+        shard.insert(key, value);
+        result
+    }
+
+    /// Copies all entries into a single tree, holding every shard's lock at once so that the copy
+    /// reflects one point in time.
+    pub fn snapshot(&self) -> SrbTreeMap<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let shards: Vec<_> = self.shards.iter().map(Self::read).collect();
+        let entries = shards
+            .iter()
+            .flat_map(|shard| shard.iter())
+            .map(|(k, v)| (k.clone(), v.clone()));
+
+        match self.depth {
+            Some(_) => {
+                SrbTreeMap::from_sorted_iter(entries).expect("shards hold ascending key ranges")
+            }
+            // the keys of hashed shards interleave
+            None => entries.collect(),
+        }
+    }
+}
+
+impl<K: Key, V> Default for ShardedSrbTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_shards() {
+        let map = ShardedSrbTreeMap::with_universe(0xffffu32);
+        for key in (0..0x10000).step_by(7) {
+            assert_eq!(map.insert(key, key), None);
+        }
+        assert_eq!(map.insert(7, 0), Some(7));
+        assert_eq!(map.remove(&14), Some(14));
+
+        // every shard got a sixteenth of the keys
+        for shard in &map.shards {
+            let len = shard.read().unwrap().len();
+            assert!(len.abs_diff(0x10000 / 7 / 16) <= 1, "{len}");
+        }
+
+        assert_eq!(map.get(&7), Some(0));
+        assert_eq!(map.get(&14), None);
+        assert!(map
+            .snapshot()
+            .keys()
+            .copied()
+            .eq((0..0x10000).step_by(7).filter(|key| *key != 14)));
+
+        let result = std::panic::catch_unwind(|| map.insert(0x10000, 0));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_hashed() {
+        // small keys all share the shard of the top digit
+        let map = ShardedSrbTreeMap::new();
+        for key in 0..1600u64 {
+            map.insert(key, ());
+        }
+        assert_eq!(map.shards[0].read().unwrap().len(), 1600);
+
+        // small sequential keys and keys with a stride of a digit both use every shard
+        for stride in [1u64, 3, 16, 256, 1 << 20] {
+            let map = ShardedSrbTreeMap::new_hashed();
+            for key in (0..1600).map(|k| k * stride) {
+                map.insert(key, ());
+            }
+
+            for shard in &map.shards {
+                let len = shard.read().unwrap().len();
+                assert!(len.abs_diff(100) <= 50, "{stride} {len}");
+            }
+            assert!(map
+                .snapshot()
+                .keys()
+                .copied()
+                .eq((0..1600).map(|k| k * stride)));
+        }
+    }
+
+    #[test]
+    fn test_update_with() {
+        let map = ShardedSrbTreeMap::with_universe(255u8);
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for key in 0..=255 {
+                        map.update_with(key, || 0u32, |count| *count += 1);
+                    }
+                });
+            }
+        });
+
+        assert!((0..=255).all(|key| map.get(&key) == Some(4)));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        // consecutive keys go to different shards
+        let keys: Vec<u32> = (0..4096).map(|k| k * 4099 % 0x10000).collect();
+        let map = ShardedSrbTreeMap::with_universe(0xffff);
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for key in &keys {
+                    map.insert(*key, ());
+                }
+            });
+
+            // keys are inserted one after another, so any snapshot holds a prefix of them
+            for _ in 0..50 {
+                let snapshot = map.snapshot();
+                let prefix = &keys[..snapshot.len()];
+                assert!(prefix.iter().all(|key| snapshot.get(key).is_some()));
+            }
+        });
+    }
+}
@@ -0,0 +1,49 @@
+use super::StorageMode;
+
+/// A report on the shape and memory use of a tree, see [`SrbTreeMap::stats`].
+///
+/// [`SrbTreeMap::stats`]: super::map::SrbTreeMap::stats
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeStats {
+    pub mode: StorageMode,
+    pub len: usize,
+    /// The number of nodes at each depth, starting with the root at index `0`.
+    pub nodes_per_depth: Vec<usize>,
+    /// The number of leafs holding a given number of entries, indexed by that number.
+    pub leaf_fill: Vec<usize>,
+    /// The number of internal nodes without any entries below them.
+    pub stumps: usize,
+    /// The number of bytes allocated for nodes, this doesn't include allocations owned by the
+    /// keys and values themselves.
+    pub heap_bytes: usize,
+}
+
+impl TreeStats {
+    pub(super) fn new(mode: StorageMode, len: usize) -> Self {
+        Self {
+            mode,
+            len,
+            nodes_per_depth: Vec::new(),
+            leaf_fill: Vec::new(),
+            stumps: 0,
+            heap_bytes: 0,
+        }
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.nodes_per_depth.iter().sum()
+    }
+
+    pub fn leafs(&self) -> usize {
+        self.leaf_fill.iter().sum()
+    }
+
+    /// Returns the number of heap bytes per stored entry, or `0.0` if the tree is empty.
+    pub fn bytes_per_entry(&self) -> f64 {
+        if self.len == 0 {
+            return 0.0;
+        }
+
+        self.heap_bytes as f64 / self.len as f64
+    }
+}
@@ -0,0 +1,455 @@
+use std::iter::Peekable;
+use std::slice;
+
+use allocator_api2::alloc::{AllocError, Allocator};
+
+use crate::key::Key;
+
+use super::node::pool::Pool;
+use super::node::Node;
+use super::B;
+
+/// The number of entries a tree stores inline before it is promoted to a trie.
+pub(super) const INLINE: usize = 8;
+
+/// The number of entries at or below which a trie is demoted to inline storage again.
+///
+/// This is well below [`INLINE`] so that a tree sitting on the boundary doesn't alternate between
+/// both modes.
+pub(super) const DEMOTE: usize = INLINE / 2;
+
+/// The mode a tree currently stores its entries in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageMode {
+    /// Few entries stored in a sorted array inside the tree itself.
+    Inline,
+    /// Entries stored in heap allocated trie nodes.
+    Trie,
+}
+
+pub(super) struct Inline<K, V> {
+    len: usize,
+    entries: [Option<(K, V)>; INLINE],
+}
+
+impl<K, V> Inline<K, V> {
+    pub const fn new() -> Self {
+        Self {
+            len: 0,
+            entries: [const { None }; INLINE],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn entries(&self) -> slice::Iter<'_, Option<(K, V)>> {
+        self.entries[..self.len].iter()
+    }
+
+    #[cfg(feature = "rayon")]
+    pub fn entries_mut(&mut self) -> slice::IterMut<'_, Option<(K, V)>> {
+        self.entries[..self.len].iter_mut()
+    }
+
+    pub fn first(&self) -> Option<(&K, &V)> {
+        self.entries().next()?.as_ref().map(|(k, v)| (k, v))
+    }
+
+    pub fn first_mut(&mut self) -> Option<(&K, &mut V)> {
+        self.entries[..self.len]
+            .first_mut()?
+            .as_mut()
+            .map(|(k, v)| (&*k, v))
+    }
+
+    pub fn last(&self) -> Option<(&K, &V)> {
+        self.entries().next_back()?.as_ref().map(|(k, v)| (k, v))
+    }
+
+    pub fn last_mut(&mut self) -> Option<(&K, &mut V)> {
+        self.entries[..self.len]
+            .last_mut()?
+            .as_mut()
+            .map(|(k, v)| (&*k, v))
+    }
+}
+
+impl<K, V> Inline<K, V> {
+    /// Takes all entries out in order, leaving this empty.
+    pub fn drain(&mut self) -> impl Iterator<Item = (K, V)> + '_ {
+        let len = std::mem::take(&mut self.len);
+        self.entries[..len].iter_mut().filter_map(Option::take)
+    }
+}
+
+impl<K: Ord, V> Inline<K, V> {
+    fn position(&self, key: &K) -> Result<usize, usize> {
+        self.entries[..self.len]
+            .binary_search_by(|entry| entry.as_ref().map(|(k, _)| k).cmp(&Some(key)))
+    }
+
+    pub fn get(&self, key: &K) -> Option<(&K, &V)> {
+        let idx = self.position(key).ok()?;
+        self.entries[idx].as_ref().map(|(k, v)| (k, v))
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<(&K, &mut V)> {
+        let idx = self.position(key).ok()?;
+        self.entries[idx].as_mut().map(|(k, v)| (&*k, v))
+    }
+
+    /// Inserts a pair, handing it back if there is no room left for it.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<(K, V)>, (K, V)> {
+        match self.position(&key) {
+            Ok(idx) => Ok(self.entries[idx].replace((key, value))),
+            Err(_) if self.len == INLINE => Err((key, value)),
+            Err(idx) => {
+                self.entries[idx..=self.len].rotate_right(1);
+                self.entries[idx] = Some((key, value));
+                self.len += 1;
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<(K, V)> {
+        let idx = self.position(key).ok()?;
+
+        let old = self.entries[idx].take();
+        self.entries[idx..self.len].rotate_left(1);
+        self.len -= 1;
+
+        old
+    }
+}
+
+pub(super) struct Trie<K, V, A: Allocator + Clone> {
+    pub root: Node<K, V, B, A>,
+    /// The number of levels above the leafs, the root is a leaf if this is zero.
+    pub height: usize,
+}
+
+impl<K, V, A: Allocator + Clone> Trie<K, V, A> {
+    pub fn len(&self) -> usize {
+        self.root.len
+    }
+}
+
+impl<K: Key, V, A: Allocator + Clone> Trie<K, V, A> {
+    pub fn new(alloc: A) -> Self {
+        Self {
+            root: Node::new_leaf(alloc),
+            height: 0,
+        }
+    }
+
+    /// Moves all entries of a full inline array into a new trie.
+    pub fn promote(inline: &mut Inline<K, V>, alloc: A, pool: &mut Pool<K, V, B, A>) -> Self {
+        let mut this = Self::new(alloc);
+        for (key, value) in inline.entries.iter_mut().filter_map(Option::take) {
+            this.insert(key, value, pool);
+        }
+
+        inline.len = 0;
+        this
+    }
+
+    /// Moves all entries of a full inline array into a new trie, moving them back if an allocation
+    /// fails.
+    pub fn try_promote(
+        inline: &mut Inline<K, V>,
+        alloc: A,
+        pool: &mut Pool<K, V, B, A>,
+    ) -> Result<Self, AllocError> {
+        let mut this = Self::new(alloc);
+        for idx in 0..inline.len {
+            let (key, _) = inline.entries[idx]
+                .as_ref()
+                .expect("inline entries are contiguous");
+            if let Err(err) = this.try_reserve_path(key) {
+                // the entries moved so far are the smallest ones, so they come back out in order
+                for entry in &mut inline.entries[..idx] {
+                    *entry = this.root.pop_first();
+                }
+
+                return Err(err);
+            }
+
+            let (key, value) = inline.entries[idx]
+                .take()
+                .expect("inline entries are contiguous");
+            this.insert(key, value, pool);
+        }
+
+        inline.len = 0;
+        Ok(this)
+    }
+
+    /// Moves all entries of a trie with few enough entries into a new inline array, the emptied
+    /// nodes go to the pool.
+    pub fn demote(mut self, pool: &mut Pool<K, V, B, A>) -> Inline<K, V> {
+        debug_assert!(self.len() <= INLINE, "too many entries to demote");
+
+        let mut inline = Inline::new();
+        self.root.drain(&mut |entry| {
+            inline.entries[inline.len] = Some(entry);
+            inline.len += 1;
+        });
+
+        pool.recycle(self.root);
+        inline
+    }
+
+    fn depth(&self) -> usize {
+        K::max_depth(B) - self.height
+    }
+
+    /// Adds levels above the root until it is at least at the given height, a bare root is
+    /// replaced instead.
+    fn grow(&mut self, height: usize, pool: &mut Pool<K, V, B, A>) {
+        if self.root.is_bare() && self.height < height {
+            let root = pool.new_at(K::max_depth(B) - height, self.root.allocator().clone());
+            pool.recycle(std::mem::replace(&mut self.root, root));
+            self.height = height;
+            return;
+        }
+
+        while self.height < height {
+            let internal = pool.new_internal(self.root.allocator().clone());
+            let root = std::mem::replace(&mut self.root, internal);
+            self.root.replace_child_at(0, Some(root));
+            self.height += 1;
+        }
+    }
+
+    /// Like [`Self::grow`], but fails instead of aborting if a new root can't be allocated, the
+    /// trie is left as is then. Returns the old root if it was bare and replaced.
+    fn try_grow(&mut self, height: usize) -> Result<Option<Node<K, V, B, A>>, AllocError> {
+        if self.root.is_bare() && self.height < height {
+            let alloc = self.root.allocator().clone();
+            let root = Node::try_new_at(K::max_depth(B) - height, alloc)?;
+            self.height = height;
+            return Ok(Some(std::mem::replace(&mut self.root, root)));
+        }
+
+        let old = self.height;
+        while self.height < height {
+            let internal = match Node::try_new_internal(self.root.allocator().clone()) {
+                Ok(internal) => internal,
+                Err(err) => {
+                    while self.height > old {
+                        self.pop_level();
+                    }
+                    return Err(err);
+                }
+            };
+            let root = std::mem::replace(&mut self.root, internal);
+            self.root.replace_child_at(0, Some(root));
+            self.height += 1;
+        }
+
+        Ok(None)
+    }
+
+    /// Replaces the root with its first child and returns the old root, this never allocates.
+    fn pop_level(&mut self) -> Node<K, V, B, A> {
+        let child = self.root.take_child(0).expect("root has a first child");
+        self.height -= 1;
+        std::mem::replace(&mut self.root, child)
+    }
+
+    /// Removes levels above the root for as long as its first child is its only one, the removed
+    /// levels go to the pool.
+    fn shrink(&mut self, pool: &mut Pool<K, V, B, A>) {
+        while self.height != 0 && self.root.child_count() == 1 && self.root.child(0).is_some() {
+            let root = self.pop_level();
+            pool.recycle(root);
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V, pool: &mut Pool<K, V, B, A>) -> Option<(K, V)> {
+        self.grow(key.height(B), pool);
+        let depth = self.depth();
+        self.root.insert(depth, key, value, pool)
+    }
+
+    /// Inserts all pairs, building the subtrees below the children of the root in parallel.
+    #[cfg(feature = "rayon")]
+    pub fn par_extend(&mut self, entries: Vec<(K, V)>, pool: &mut Pool<K, V, B, A>)
+    where
+        K: Send,
+        V: Send,
+        A: Send,
+    {
+        let height = entries.iter().map(|(key, _)| key.height(B)).max();
+        self.grow(height.unwrap_or(0), pool);
+        let depth = self.depth();
+        self.root.par_insert(depth, entries, pool);
+    }
+
+    /// Inserts pairs from `batch` for as long as their keys are accepted and fit into the trie at
+    /// its current height, see [`Node::insert_run`].
+    pub fn insert_run<I, F>(
+        &mut self,
+        batch: &mut Peekable<I>,
+        accept: &F,
+        pool: &mut Pool<K, V, B, A>,
+    ) where
+        I: Iterator<Item = (K, V)>,
+        F: Fn(&K) -> bool,
+    {
+        if batch
+            .peek()
+            .is_some_and(|(key, _)| key.height(B) <= self.height)
+        {
+            let depth = self.depth();
+            self.root.insert_run(depth, batch, accept, pool);
+        }
+    }
+
+    /// Creates all nodes holding the keys in `lo..=hi`, given by their [`Key::to_usize`] value.
+    pub fn reserve_range(&mut self, lo: usize, hi: usize, pool: &mut Pool<K, V, B, A>) {
+        self.grow(hi.checked_ilog(B).unwrap_or(0) as usize, pool);
+        let depth = self.depth();
+        self.root.reserve_range(depth, lo, hi, pool);
+    }
+
+    /// Allocates all nodes needed to insert the given key without allocating. If this fails, only
+    /// the nodes and levels it added are removed again, without allocating.
+    pub fn try_reserve_path(&mut self, key: &K) -> Result<(), AllocError> {
+        let height = self.height;
+        let replaced = self.try_grow(key.height(B))?;
+
+        let depth = self.depth();
+        let result = self.root.try_reserve_path(depth, key);
+        if result.is_err() {
+            // the added levels are dropped, growing the pool to hold them might allocate
+            match replaced {
+                Some(root) => {
+                    self.root = root;
+                    self.height = height;
+                }
+                None => {
+                    while self.height > height {
+                        self.pop_level();
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    pub fn remove(&mut self, key: &K, pool: &mut Pool<K, V, B, A>) -> Option<(K, V)> {
+        if key.height(B) > self.height {
+            return None;
+        }
+
+        let old = self.root.remove(self.depth(), key);
+        if old.is_some() {
+            self.shrink(pool);
+        }
+
+        old
+    }
+
+    pub fn get(&self, key: &K) -> Option<(&K, &V)> {
+        if key.height(B) > self.height {
+            return None;
+        }
+
+        self.root.get(self.depth(), key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<(&mut K, &mut V)> {
+        if key.height(B) > self.height {
+            return None;
+        }
+
+        let depth = self.depth();
+        self.root.get_mut(depth, key)
+    }
+}
+
+pub(super) enum Storage<K, V, A: Allocator + Clone> {
+    Inline(Inline<K, V>),
+    Trie(Trie<K, V, A>),
+}
+
+impl<K, V, A: Allocator + Clone> Storage<K, V, A> {
+    pub fn mode(&self) -> StorageMode {
+        match self {
+            Storage::Inline(_) => StorageMode::Inline,
+            Storage::Trie(_) => StorageMode::Trie,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Storage::Inline(inline) => inline.len(),
+            Storage::Trie(trie) => trie.len(),
+        }
+    }
+}
+
+impl<K: Key, V, A: Allocator + Clone> Storage<K, V, A> {
+    /// Switches a trie to inline storage, see [`Trie::demote`].
+    pub fn demote(&mut self, pool: &mut Pool<K, V, B, A>) {
+        if let Storage::Trie(trie) = std::mem::replace(self, Storage::Inline(Inline::new())) {
+            *self = Storage::Inline(trie.demote(pool));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use allocator_api2::alloc::Global;
+
+    use super::*;
+
+    #[test]
+    fn test_inline_sorted() {
+        let mut inline = Inline::<u32, u32>::new();
+
+        for key in [5, 1, 7, 3] {
+            assert_eq!(inline.insert(key, key * 10), Ok(None));
+        }
+        assert_eq!(inline.insert(3, 0), Ok(Some((3, 30))));
+
+        let keys: Vec<_> = inline.entries().flatten().map(|(k, _)| *k).collect();
+        assert_eq!(keys, [1, 3, 5, 7]);
+
+        assert_eq!(inline.remove(&5), Some((5, 50)));
+        assert_eq!(inline.remove(&5), None);
+        assert_eq!(inline.first(), Some((&1, &10)));
+        assert_eq!(inline.last(), Some((&7, &70)));
+    }
+
+    #[test]
+    fn test_inline_full() {
+        let mut inline = Inline::<u32, ()>::new();
+
+        for key in 0..INLINE as u32 {
+            assert_eq!(inline.insert(key, ()), Ok(None));
+        }
+        assert_eq!(inline.insert(0, ()), Ok(Some((0, ()))));
+        assert_eq!(inline.insert(100, ()), Err((100, ())));
+
+        let trie = Trie::promote(&mut inline, Global, &mut Pool::new());
+        assert_eq!(inline.len(), 0);
+        assert_eq!(trie.len(), INLINE);
+
+        let mut pool = Pool::new();
+        pool.set_limit(1);
+        let inline = trie.demote(&mut pool);
+        assert_eq!(inline.len(), INLINE);
+        assert!(inline
+            .entries()
+            .flatten()
+            .map(|(k, _)| *k)
+            .eq(0..INLINE as u32));
+        assert_eq!(pool.len(), 1);
+    }
+}